@@ -0,0 +1,44 @@
+//! Compares the plain and the fair read-write locks under a read-heavy load.
+//!
+//! Run with `cargo run --example lock_contention --features test-util`.
+
+use std::time::Duration;
+use stratum_apps::custom_rwlock::{ContentionHarness, ContentionReport, FairRwLock, RwLock};
+
+fn print(name: &str, report: &ContentionReport) {
+    let max_writer_wait = report
+        .writers
+        .iter()
+        .map(|stats| stats.max_wait)
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{name}: {:.0} ops/s, max wait {:?}, max writer wait {:?}, {} starved threads",
+        report.throughput(),
+        report.max_wait(),
+        max_writer_wait,
+        report.starved_threads()
+    );
+}
+
+fn main() {
+    let harness = ContentionHarness::new(8, 2).duration(Duration::from_secs(1));
+
+    let lock = RwLock::new(vec![0u64; 64]);
+    let report = harness.run(
+        || {
+            lock.super_safe_read(|values| values.iter().sum::<u64>());
+        },
+        || lock.super_safe_write(|values| values.iter_mut().for_each(|v| *v += 1)),
+    );
+    print("RwLock", &report);
+
+    let fair = FairRwLock::new(vec![0u64; 64]);
+    let report = harness.run(
+        || {
+            fair.safe_read(|values| values.iter().sum::<u64>());
+        },
+        || fair.safe_write(|values| values.iter_mut().for_each(|v| *v += 1)),
+    );
+    print("FairRwLock", &report);
+}
@@ -0,0 +1,276 @@
+//! Bookkeeping run on every acquisition and release of a [`RwLock`].
+//!
+//! Both the closure-based accessors and the explicit-release guards go through
+//! [`RwLock::on_acquire`] and [`RwLock::on_release`], so diagnostics only need to be hooked in
+//! here.
+//!
+//! Counters never produce absurd values on long-running processes. Monotonic counters, such as
+//! the sampling counter, wrap around, which only shifts which acquisitions get sampled. Gauges
+//! going up and down, such as the outstanding acquisition counts, saturate: they stick at the
+//! maximum instead of wrapping to zero, and a release without a matching acquisition, which would
+//! be a bookkeeping bug, leaves them at zero instead of underflowing, and is logged in debug
+//! builds.
+
+use super::{
+    holders::{Held, HeldMode},
+    watchdog::Watched,
+    RwLock,
+};
+use std::{
+    panic::Location,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+// Acquisitions not released yet, across every lock.
+static OUTSTANDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Panics if any [`RwLock`] acquisition, on any lock, has not been released yet.
+///
+/// Meant for test teardown: unlike the unreleased guard panic, it also catches guards that were
+/// leaked (e.g. with [`std::mem::forget`]) and thus never dropped.
+#[track_caller]
+pub fn assert_no_outstanding_guards() {
+    let outstanding = OUTSTANDING.load(Ordering::Relaxed);
+    assert!(
+        outstanding == 0,
+        "{outstanding} RwLock acquisitions are still outstanding"
+    );
+}
+
+// Increments a gauge, saturating at the maximum.
+fn gauge_increment(gauge: &AtomicUsize) {
+    let _ = gauge.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+        Some(n.saturating_add(1))
+    });
+}
+
+// Decrements a gauge, leaving it at zero on underflow. Returns whether it underflowed.
+fn gauge_decrement(gauge: &AtomicUsize) -> bool {
+    gauge
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+        .is_err()
+}
+
+/// Sampled hold time tripwire, see [`RwLock::with_hold_warn_sampled`].
+#[derive(Debug)]
+pub(crate) struct HoldWarn {
+    threshold: Duration,
+    sample_rate: u64,
+    acquisitions: AtomicU64,
+}
+
+impl HoldWarn {
+    pub(crate) fn new(threshold: Duration, sample_rate: u64) -> Self {
+        HoldWarn {
+            threshold,
+            sample_rate: sample_rate.max(1),
+            acquisitions: AtomicU64::new(0),
+        }
+    }
+
+    // Decided at acquisition, so unsampled acquisitions never pay for `Instant::now()`.
+    fn sample(&self) -> bool {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed) % self.sample_rate == 0
+    }
+}
+
+/// State of one acquisition, from the moment the lock is acquired until it is released.
+pub(crate) struct Acquired {
+    _held: Held,
+    _watched: Option<Watched>,
+    #[cfg(feature = "test-util")]
+    _registered: super::registry::Registered,
+    // Whether this is a read counted by the priority tracking.
+    tracked_read: bool,
+    // Whether the thread was already panicking, see `poisons`.
+    panicking: bool,
+    #[cfg(debug_assertions)]
+    write: bool,
+    started: Option<Instant>,
+    #[cfg(feature = "profile")]
+    site: (&'static Location<'static>, Instant),
+}
+
+impl Acquired {
+    // Whether releasing a write acquisition now poisons the lock: as for the std guard, the
+    // thread started panicking while it held the lock.
+    pub(crate) fn poisons(&self) -> bool {
+        !self.panicking && std::thread::panicking()
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Creates a lock that logs a warning when a guard is held longer than `threshold`.
+    ///
+    /// Only roughly one in `sample_rate` acquisitions is timed, which keeps the overhead of the
+    /// check negligible on very hot locks while still catching recurring long holds.
+    pub fn with_hold_warn_sampled(value: T, threshold: Duration, sample_rate: u64) -> Self {
+        let mut lock = RwLock::new(value);
+        lock.hold_warn = Some(HoldWarn::new(threshold, sample_rate));
+        lock
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Number of acquisitions of this lock not released yet, guards and closures alike.
+    pub fn outstanding_guards(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    #[track_caller]
+    pub(crate) fn on_acquire(&self, mode: HeldMode) -> Acquired {
+        let started = match &self.hold_warn {
+            Some(hold_warn) if hold_warn.sample() => Some(Instant::now()),
+            _ => None,
+        };
+        let site = Location::caller();
+        if let Some(trace) = &self.trace {
+            trace.record(site, mode);
+        }
+        if let Some(sites) = &self.sites {
+            sites.record(site);
+        }
+        #[cfg(feature = "fairness")]
+        if let Some(fairness) = &self.fairness {
+            fairness.record();
+        }
+        let tracked_read = match &self.priority {
+            Some(priority) if mode == HeldMode::Read => {
+                priority.read_acquired();
+                true
+            }
+            _ => false,
+        };
+        let watched = self
+            .deadlock
+            .as_ref()
+            .and_then(|deadlock| deadlock.watch(|| self.describe(), site));
+        gauge_increment(&self.outstanding);
+        gauge_increment(&OUTSTANDING);
+        #[cfg(debug_assertions)]
+        if mode == HeldMode::Write {
+            self.writer
+                .store(super::holders::current_thread_index(), Ordering::Relaxed);
+        }
+        Acquired {
+            _held: Held::new(self.addr(), mode),
+            _watched: watched,
+            #[cfg(feature = "test-util")]
+            _registered: super::registry::Registered::new(
+                self.label,
+                self.addr(),
+                mode == HeldMode::Write,
+                site,
+            ),
+            tracked_read,
+            panicking: std::thread::panicking(),
+            #[cfg(debug_assertions)]
+            write: mode == HeldMode::Write,
+            started,
+            #[cfg(feature = "profile")]
+            site: (site, Instant::now()),
+        }
+    }
+
+    pub(crate) fn on_release(&self, acquired: Acquired) {
+        #[cfg(debug_assertions)]
+        if acquired.write {
+            self.writer.store(0, Ordering::Relaxed);
+        }
+        if let (Some(priority), true) = (&self.priority, acquired.tracked_read) {
+            priority.read_released();
+        }
+        let underflowed = gauge_decrement(&self.outstanding) | gauge_decrement(&OUTSTANDING);
+        #[cfg(debug_assertions)]
+        if underflowed {
+            tracing::warn!(
+                "{} released more often than acquired, outstanding count clamped at zero",
+                self.describe()
+            );
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = underflowed;
+        #[cfg(feature = "profile")]
+        self.profile
+            .record(acquired.site.0, acquired.site.1.elapsed());
+        if let (Some(hold_warn), Some(started)) = (&self.hold_warn, acquired.started) {
+            let held_for = started.elapsed();
+            if held_for > hold_warn.threshold {
+                tracing::warn!(
+                    "{} held for {:?}, longer than the {:?} threshold",
+                    self.describe(),
+                    held_for,
+                    hold_warn.threshold
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hold_warn_samples_acquisitions() {
+        let lock = RwLock::with_hold_warn_sampled(0u8, Duration::from_millis(1), 10);
+        let timed = (0..1000)
+            .filter(|_| {
+                let acquired = lock.on_acquire(HeldMode::Read);
+                let timed = acquired.started.is_some();
+                lock.on_release(acquired);
+                timed
+            })
+            .count();
+        assert_eq!(timed, 100);
+
+        // Guards and closures go through the same sampling.
+        lock.read().unwrap().release();
+        lock.safe_write(|_| ()).unwrap();
+        let acquisitions = lock
+            .hold_warn
+            .as_ref()
+            .unwrap()
+            .acquisitions
+            .load(Ordering::Relaxed);
+        assert_eq!(acquisitions, 1002);
+    }
+
+    #[test]
+    fn test_unconfigured_lock_is_never_timed() {
+        let lock = RwLock::new(0u8);
+        let acquired = lock.on_acquire(HeldMode::Write);
+        assert!(acquired.started.is_none());
+        lock.on_release(acquired);
+    }
+
+    #[test]
+    fn test_gauges_saturate() {
+        let gauge = AtomicUsize::new(usize::MAX - 1);
+        gauge_increment(&gauge);
+        gauge_increment(&gauge);
+        assert_eq!(gauge.load(Ordering::Relaxed), usize::MAX);
+
+        let lock = RwLock::new(0u8);
+        let acquired = lock.on_acquire(HeldMode::Read);
+        // Simulate a release outpacing its acquisition.
+        lock.outstanding.store(0, Ordering::Relaxed);
+        lock.on_release(acquired);
+        assert_eq!(lock.outstanding_guards(), 0);
+    }
+
+    #[test]
+    fn test_outstanding_guards_count() {
+        let lock = RwLock::new(0u8);
+        let first = lock.read().unwrap();
+        let second = lock.read().unwrap();
+        assert_eq!(lock.outstanding_guards(), 2);
+        first.release();
+        second.release();
+        assert_eq!(lock.outstanding_guards(), 0);
+
+        std::mem::forget(lock.write().unwrap());
+        assert_eq!(lock.outstanding_guards(), 1);
+    }
+}
@@ -0,0 +1,476 @@
+//! Acquisition of many locks of the same type at once, or of whichever of them is free first,
+//! and of locks of different types with [`multi_lock!`](crate::multi_lock).
+//!
+//! Locks are always acquired in address order, whatever order the caller lists them in. As long
+//! as every multi-lock acquisition goes through these helpers, two callers can never wait on each
+//! other in a cycle.
+
+use super::{
+    retry::{ExponentialBackoff, LockBackoff},
+    timeout::poll,
+    ReadGuard, RwLock, WriteGuard,
+};
+use std::{
+    sync::{PoisonError, TryLockError},
+    time::Duration,
+};
+
+// Indices of `locks` sorted by lock address, panicking if a lock is listed twice.
+fn address_order<T>(locks: &[RwLock<T>]) -> Vec<usize> {
+    key_order(locks, |_| ())
+}
+
+// Indices of `locks` sorted by `key`, ties broken by address, panicking if a lock is listed
+// twice.
+fn key_order<T, K: Ord>(locks: &[RwLock<T>], key: impl Fn(&RwLock<T>) -> K) -> Vec<usize> {
+    let mut keyed: Vec<(K, usize, usize)> = locks
+        .iter()
+        .enumerate()
+        .map(|(i, lock)| (key(lock), lock.addr(), i))
+        .collect();
+    keyed.sort();
+    assert!(
+        keyed.windows(2).all(|w| w[0].1 != w[1].1),
+        "the same lock was listed twice"
+    );
+    keyed.into_iter().map(|(_, _, i)| i).collect()
+}
+
+// Acquires an exclusive lock on every lock of `locks`, in `order`, returning the guards in the
+// order of `locks`.
+fn write_in_order<T>(
+    locks: &[RwLock<T>],
+    order: Vec<usize>,
+) -> Result<Vec<WriteGuard<'_, T>>, PoisonError<()>> {
+    let mut slots: Vec<Option<WriteGuard<'_, T>>> = locks.iter().map(|_| None).collect();
+    for i in order {
+        match locks[i].write() {
+            Ok(guard) => slots[i] = Some(guard),
+            Err(_) => {
+                slots.into_iter().flatten().for_each(WriteGuard::release);
+                return Err(PoisonError::new(()));
+            }
+        }
+    }
+    Ok(slots.into_iter().flatten().collect())
+}
+
+/// Acquires a shared lock on every lock of `locks` and runs `f` over the guards.
+///
+/// The guards are handed to `f` in the order of `locks`. They are all released once `f` returns,
+/// or while unwinding if `f` panics. If one of the locks is poisoned, the locks acquired so far
+/// are released and `f` is not called.
+pub fn with_all_read<T, F, R>(locks: &[RwLock<T>], f: F) -> Result<R, PoisonError<()>>
+where
+    F: FnOnce(&[ReadGuard<'_, T>]) -> R,
+{
+    let mut slots: Vec<Option<ReadGuard<'_, T>>> = locks.iter().map(|_| None).collect();
+    for i in address_order(locks) {
+        match locks[i].read() {
+            Ok(guard) => slots[i] = Some(guard),
+            Err(_) => {
+                slots.into_iter().flatten().for_each(ReadGuard::release);
+                return Err(PoisonError::new(()));
+            }
+        }
+    }
+    let guards: Vec<ReadGuard<'_, T>> = slots.into_iter().flatten().collect();
+    let result = f(&guards);
+    guards.into_iter().for_each(ReadGuard::release);
+    Ok(result)
+}
+
+/// Acquires an exclusive lock on every lock of `locks` and runs `f` over the guards.
+///
+/// Exclusive counterpart of [`with_all_read`], with the same ordering and release guarantees.
+pub fn with_all_write<T, F, R>(locks: &[RwLock<T>], f: F) -> Result<R, PoisonError<()>>
+where
+    F: FnOnce(&mut [WriteGuard<'_, T>]) -> R,
+{
+    let mut guards = write_in_order(locks, address_order(locks))?;
+    let result = f(&mut guards);
+    guards.into_iter().for_each(WriteGuard::release);
+    Ok(result)
+}
+
+/// Acquires an exclusive lock on every lock of `locks`, in the order given by `key`, and returns
+/// the guards in the order of `locks`.
+///
+/// Locks with the same key are acquired in address order. Unlike addresses, a domain key (e.g.
+/// an account id) gives an order that is stable across runs and easy to reason about, but it
+/// only prevents deadlocks if every call site locking the same locks uses the same key function.
+/// If one of the locks is poisoned, the locks acquired so far are released.
+///
+/// `key` is called once per lock, before any of them is acquired by this function.
+pub fn lock_all_write_by<T, K, F>(
+    locks: &[RwLock<T>],
+    key: F,
+) -> Result<Vec<WriteGuard<'_, T>>, PoisonError<()>>
+where
+    K: Ord,
+    F: Fn(&RwLock<T>) -> K,
+{
+    write_in_order(locks, key_order(locks, key))
+}
+
+/// Acquires an exclusive lock on whichever of `locks` is free first.
+///
+/// Every lock is polled in turn with a non-blocking attempt, backing off between rounds with the
+/// backoff of the first lock, until one is acquired or `timeout` has elapsed. A timeout too large
+/// to hold a deadline, such as [`Duration::MAX`], never elapses. Returns the index of the
+/// acquired lock in `locks` with its guard, or `None` on timeout. Poisoned locks are never
+/// acquired.
+#[track_caller]
+pub fn try_acquire_any<'a, T>(
+    locks: &[&'a RwLock<T>],
+    timeout: Duration,
+) -> Option<(usize, WriteGuard<'a, T>)> {
+    locks.iter().for_each(|lock| lock.debug_assert_writable());
+    let backoff = match locks.first() {
+        Some(lock) => lock.backoff(),
+        None => LockBackoff::Default(ExponentialBackoff::default()),
+    };
+    let (i, inner) = poll(timeout, &backoff, || {
+        locks
+            .iter()
+            .enumerate()
+            .find_map(|(i, lock)| Some((i, lock.inner.try_write().ok()?)))
+            .ok_or(TryLockError::WouldBlock)
+    })
+    .ok()?;
+    Some((i, WriteGuard::new(locks[i], inner)))
+}
+
+/// Acquires several locks, possibly of different types, each for reading or for writing.
+///
+/// ```
+/// use stratum_apps::{custom_rwlock::RwLock, multi_lock};
+///
+/// let (jobs, shares) = (RwLock::new(vec![1u32]), RwLock::new(0u64));
+/// let (jobs, mut shares) = multi_lock!(read jobs, write shares).unwrap();
+/// *shares += jobs.len() as u64;
+/// jobs.release();
+/// shares.release();
+/// ```
+///
+/// Evaluates to a tuple of [`ReadGuard`]s and [`WriteGuard`]s in the order the locks are listed,
+/// which must then be released. The locks themselves are acquired in address order, like the
+/// other batch helpers, so two `multi_lock!` over the same locks never deadlock, whatever order
+/// each one lists them in. If one of the locks is poisoned, the locks acquired so far are
+/// released and a [`PoisonError`] is returned. Up to six locks can be listed.
+///
+/// # Panics
+///
+/// Panics if the same lock is listed twice.
+#[macro_export]
+macro_rules! multi_lock {
+    (@slot read $lock:expr) => {
+        $crate::custom_rwlock::ReadSlot::new(&$lock)
+    };
+    (@slot write $lock:expr) => {
+        $crate::custom_rwlock::WriteSlot::new(&$lock)
+    };
+    ($($mode:ident $lock:expr),+ $(,)?) => {
+        $crate::custom_rwlock::MultiLock::acquire(($($crate::multi_lock!(@slot $mode $lock),)+))
+    };
+}
+
+/// One lock of a [`multi_lock!`](crate::multi_lock) acquisition, see [`MultiLock`].
+#[doc(hidden)]
+pub trait Slot {
+    type Guard;
+
+    // Type-erased part, to sort slots of different types together.
+    #[doc(hidden)]
+    fn erased(&mut self) -> &mut dyn ErasedSlot;
+
+    #[doc(hidden)]
+    fn take(&mut self) -> Self::Guard;
+}
+
+#[doc(hidden)]
+pub trait ErasedSlot {
+    fn addr(&self) -> usize;
+    // Acquires the lock, returns whether it is healthy.
+    fn acquire(&mut self) -> bool;
+    fn release(&mut self);
+}
+
+/// A lock to acquire for reading in a [`multi_lock!`](crate::multi_lock).
+#[doc(hidden)]
+pub struct ReadSlot<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    guard: Option<ReadGuard<'a, T>>,
+}
+
+impl<'a, T: ?Sized> ReadSlot<'a, T> {
+    pub fn new(lock: &'a RwLock<T>) -> Self {
+        ReadSlot { lock, guard: None }
+    }
+}
+
+impl<T: ?Sized> ErasedSlot for ReadSlot<'_, T> {
+    fn addr(&self) -> usize {
+        self.lock.addr()
+    }
+
+    fn acquire(&mut self) -> bool {
+        self.guard = self.lock.read().ok();
+        self.guard.is_some()
+    }
+
+    fn release(&mut self) {
+        if let Some(guard) = self.guard.take() {
+            guard.release();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Slot for ReadSlot<'a, T> {
+    type Guard = ReadGuard<'a, T>;
+
+    fn erased(&mut self) -> &mut dyn ErasedSlot {
+        self
+    }
+
+    fn take(&mut self) -> ReadGuard<'a, T> {
+        self.guard.take().expect("acquired before being taken")
+    }
+}
+
+/// A lock to acquire for writing in a [`multi_lock!`](crate::multi_lock).
+#[doc(hidden)]
+pub struct WriteSlot<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    guard: Option<WriteGuard<'a, T>>,
+}
+
+impl<'a, T: ?Sized> WriteSlot<'a, T> {
+    pub fn new(lock: &'a RwLock<T>) -> Self {
+        WriteSlot { lock, guard: None }
+    }
+}
+
+impl<T: ?Sized> ErasedSlot for WriteSlot<'_, T> {
+    fn addr(&self) -> usize {
+        self.lock.addr()
+    }
+
+    fn acquire(&mut self) -> bool {
+        self.guard = self.lock.write().ok();
+        self.guard.is_some()
+    }
+
+    fn release(&mut self) {
+        if let Some(guard) = self.guard.take() {
+            guard.release();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Slot for WriteSlot<'a, T> {
+    type Guard = WriteGuard<'a, T>;
+
+    fn erased(&mut self) -> &mut dyn ErasedSlot {
+        self
+    }
+
+    fn take(&mut self) -> WriteGuard<'a, T> {
+        self.guard.take().expect("acquired before being taken")
+    }
+}
+
+// Acquires every slot in address order, releasing the acquired ones if a lock is poisoned.
+fn acquire_slots(slots: &mut [&mut dyn ErasedSlot]) -> Result<(), PoisonError<()>> {
+    let mut order: Vec<usize> = (0..slots.len()).collect();
+    order.sort_by_key(|&i| slots[i].addr());
+    assert!(
+        order
+            .windows(2)
+            .all(|w| slots[w[0]].addr() != slots[w[1]].addr()),
+        "the same lock was listed twice"
+    );
+    for (acquired, &i) in order.iter().enumerate() {
+        if !slots[i].acquire() {
+            for &j in order[..acquired].iter().rev() {
+                slots[j].release();
+            }
+            return Err(PoisonError::new(()));
+        }
+    }
+    Ok(())
+}
+
+/// Tuple of slots acquired together by [`multi_lock!`](crate::multi_lock).
+#[doc(hidden)]
+pub trait MultiLock {
+    type Guards;
+
+    fn acquire(self) -> Result<Self::Guards, PoisonError<()>>;
+}
+
+macro_rules! impl_multi_lock {
+    ($($slot:ident . $i:tt),+) => {
+        impl<$($slot: Slot),+> MultiLock for ($($slot,)+) {
+            type Guards = ($($slot::Guard,)+);
+
+            fn acquire(mut self) -> Result<Self::Guards, PoisonError<()>> {
+                acquire_slots(&mut [$(self.$i.erased()),+])?;
+                Ok(($(self.$i.take(),)+))
+            }
+        }
+    };
+}
+
+impl_multi_lock!(A.0);
+impl_multi_lock!(A.0, B.1);
+impl_multi_lock!(A.0, B.1, C.2);
+impl_multi_lock!(A.0, B.1, C.2, D.3);
+impl_multi_lock!(A.0, B.1, C.2, D.3, E.4);
+impl_multi_lock!(A.0, B.1, C.2, D.3, E.4, F.5);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_rwlock::GuardBundle;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        thread,
+    };
+
+    #[test]
+    fn test_multi_lock_in_any_order() {
+        let (count, name, log) = (
+            RwLock::new(0u32),
+            RwLock::new(String::from("pool")),
+            RwLock::new(Vec::<u32>::new()),
+        );
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..500 {
+                    let mut guards = GuardBundle::new(
+                        crate::multi_lock!(read name, write count, write log).unwrap(),
+                    );
+                    let (name, count, log) = guards.guards_mut();
+                    **count += 1;
+                    log.push(name.len() as u32);
+                    guards.release();
+                }
+            });
+            for _ in 0..500 {
+                let mut guards = GuardBundle::new(
+                    crate::multi_lock!(write log, read count, write name).unwrap(),
+                );
+                let (log, count, name) = guards.guards_mut();
+                log.push(**count);
+                name.truncate(4);
+                guards.release();
+            }
+        });
+        assert_eq!(count.into_inner(), 500);
+        assert_eq!(log.into_inner().len(), 1000);
+        assert_eq!(name.into_inner(), "pool");
+    }
+
+    #[test]
+    fn test_multi_lock_releases_on_poison() {
+        let (a, b) = (RwLock::new(0u8), RwLock::new(0u16));
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            b.super_safe_write(|_| panic!("poison the lock"));
+        }));
+        assert!(crate::multi_lock!(write a, read b).is_err());
+        assert!(a.inner.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_with_all_read_sees_every_shard() {
+        let shards: Vec<RwLock<u32>> = (1..=5).map(RwLock::new).collect();
+        let values = with_all_read(&shards, |guards| {
+            guards.iter().map(|guard| **guard).collect::<Vec<_>>()
+        })
+        .unwrap();
+        assert_eq!(values, vec![1, 2, 3, 4, 5]);
+        assert!(shards.iter().all(|shard| shard.inner.try_write().is_ok()));
+    }
+
+    #[test]
+    fn test_with_all_read_releases_on_panic() {
+        let shards: Vec<RwLock<u32>> = (0..5).map(RwLock::new).collect();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            with_all_read(&shards, |_| panic!("reader failed"))
+        }));
+        assert!(result.is_err());
+        // Read locks do not poison, and every shard is free again.
+        assert!(shards.iter().all(|shard| shard.inner.try_write().is_ok()));
+    }
+
+    #[test]
+    fn test_with_all_write_mutates_every_shard() {
+        let shards: Vec<RwLock<u32>> = (0..5).map(RwLock::new).collect();
+        with_all_write(&shards, |guards| {
+            for guard in guards.iter_mut() {
+                **guard += 10;
+            }
+        })
+        .unwrap();
+        let values: Vec<u32> = shards.iter().map(|s| s.super_safe_read(|v| *v)).collect();
+        assert_eq!(values, vec![10, 11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn test_poisoned_shard_aborts_and_releases() {
+        let shards: Vec<RwLock<u32>> = (0..3).map(RwLock::new).collect();
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            shards[1].super_safe_write(|_| panic!("poison the shard"));
+        }));
+        assert!(with_all_read(&shards, |_| ()).is_err());
+        assert!(shards[0].inner.try_write().is_ok());
+        assert!(shards[2].inner.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_try_acquire_any_returns_the_free_lock() {
+        let shards: Vec<RwLock<u32>> = (0..4).map(RwLock::new).collect();
+        let held: Vec<_> = [0, 1, 3]
+            .iter()
+            .map(|&i| shards[i].write().unwrap())
+            .collect();
+        let refs: Vec<&RwLock<u32>> = shards.iter().collect();
+
+        let (index, guard) = try_acquire_any(&refs, Duration::from_secs(1)).unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(*guard, 2);
+        guard.release();
+
+        let busy = [refs[0], refs[1]];
+        assert!(try_acquire_any(&busy, Duration::from_millis(10)).is_none());
+        held.into_iter().for_each(WriteGuard::release);
+        let (index, guard) = try_acquire_any(&busy, Duration::MAX).unwrap();
+        assert_eq!(index, 0);
+        guard.release();
+    }
+
+    #[test]
+    fn test_lock_all_write_by_same_key_does_not_deadlock() {
+        let accounts: Vec<RwLock<(u32, i64)>> = (0..4).map(|id| RwLock::new((id, 100))).collect();
+        let by_id = |account: &RwLock<(u32, i64)>| account.super_safe_read(|(id, _)| *id);
+        thread::scope(|s| {
+            for (from, to) in [(0, 3), (3, 0), (1, 2), (2, 1)] {
+                let accounts = &accounts;
+                s.spawn(move || {
+                    for _ in 0..200 {
+                        let mut guards = lock_all_write_by(accounts, by_id).unwrap();
+                        guards[from].1 -= 1;
+                        guards[to].1 += 1;
+                        guards.into_iter().for_each(WriteGuard::release);
+                    }
+                });
+            }
+        });
+        let total: i64 = accounts
+            .iter()
+            .map(|a| a.super_safe_read(|(_, b)| *b))
+            .sum();
+        assert_eq!(total, 400);
+    }
+}
@@ -0,0 +1,290 @@
+//! Builder combining the optional behaviors of a [`RwLock`].
+
+use super::{
+    acquisition::HoldWarn,
+    priority::PriorityTracking,
+    retry::NewBackoff,
+    site_stats::SiteCounters,
+    snapshot::{NewSnapshot, StaleSnapshot},
+    timeout::TimeoutCounters,
+    trace::AcquisitionTrace,
+    watchdog::{Deadlock, DeadlockWatch, OnDeadlock},
+    Backoff, RwLock,
+};
+use std::{
+    fmt,
+    panic::{RefUnwindSafe, UnwindSafe},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Invalid combination of options passed to a [`RwLockBuilder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// `hold_warn_sampled` was given a sample rate of zero, so no acquisition would be timed.
+    ZeroSampleRate,
+    /// `label` was given an empty label.
+    EmptyLabel,
+    /// `deadlock_timeout` was given a zero timeout, so every acquisition would be reported.
+    ZeroDeadlockTimeout,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroSampleRate => write!(f, "Hold warning sample rate must be at least 1"),
+            Self::EmptyLabel => write!(f, "Lock label must not be empty"),
+            Self::ZeroDeadlockTimeout => write!(f, "Deadlock timeout must not be zero"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Configures the optional behaviors of a [`RwLock`], obtained with [`RwLock::builder`].
+///
+/// Every option defaults to disabled, so `RwLock::builder(value).build()` gives the same lock as
+/// [`RwLock::new`]. Options are only checked against each other in [`RwLockBuilder::build`].
+#[derive(Debug)]
+pub struct RwLockBuilder<T> {
+    value: T,
+    label: Option<&'static str>,
+    hold_warn: Option<(Duration, u64)>,
+    deadlock_timeout: Option<Duration>,
+    on_deadlock: Option<OnDeadlock>,
+    priority_tracking: bool,
+    trace_capacity: usize,
+    site_stats_capacity: usize,
+    timeout_probe: Option<Duration>,
+    backoff: Option<NewBackoff>,
+    #[cfg(feature = "profile")]
+    profile_capacity: usize,
+    #[cfg(feature = "fairness")]
+    fairness_metrics: bool,
+    snapshot: Option<NewSnapshot<T>>,
+}
+
+impl<T> RwLockBuilder<T> {
+    /// Names the lock in diagnostics (hold time warnings, unreleased guard panics).
+    pub fn label(mut self, label: &'static str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Logs a warning when a guard is held longer than `threshold`, timing roughly one in
+    /// `sample_rate` acquisitions.
+    pub fn hold_warn_sampled(mut self, threshold: Duration, sample_rate: u64) -> Self {
+        self.hold_warn = Some((threshold, sample_rate));
+        self
+    }
+
+    /// Reports guards held longer than `timeout` while they are still held, see
+    /// [`RwLock::with_deadlock_timeout`].
+    pub fn deadlock_timeout(mut self, timeout: Duration) -> Self {
+        self.deadlock_timeout = Some(timeout);
+        self
+    }
+
+    /// Hands the guards held past the deadlock timeout to `on_deadlock` instead of logging them.
+    ///
+    /// Runs on the watchdog thread, so it must not wait for the reported guard to be released.
+    /// Call [`std::process::abort`] from it to stop a hung process. Unused without a
+    /// [`RwLockBuilder::deadlock_timeout`].
+    pub fn on_deadlock<F>(mut self, on_deadlock: F) -> Self
+    where
+        F: Fn(&Deadlock) + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    {
+        self.on_deadlock = Some(OnDeadlock(Arc::new(on_deadlock)));
+        self
+    }
+
+    /// Tracks readers and waiting priority writers, to detect priority inversion, see
+    /// [`RwLock::waiting_writer_priority_blocked`].
+    ///
+    /// Only writers acquiring the lock with [`RwLock::write_priority`] count as priority writers.
+    /// Every read then updates a shared counter, which costs some contention on hot locks.
+    pub fn priority_tracking(mut self) -> Self {
+        self.priority_tracking = true;
+        self
+    }
+
+    /// Keeps the last `capacity` acquisitions, see [`RwLock::trace`].
+    ///
+    /// The buffer is allocated up front, so recording never reallocates.
+    pub fn trace_capacity(mut self, capacity: usize) -> Self {
+        self.trace_capacity = capacity;
+        self
+    }
+
+    /// Counts acquisitions per call site for up to `capacity` distinct sites, see
+    /// [`RwLock::site_stats`].
+    ///
+    /// Cheap enough to stay enabled in release builds. The table is allocated up front, so
+    /// counting never allocates.
+    pub fn site_stats_capacity(mut self, capacity: usize) -> Self {
+        self.site_stats_capacity = capacity;
+        self
+    }
+
+    /// Records the timeouts of timed acquisitions, see [`RwLock::timeout_stats`].
+    ///
+    /// After a timeout, the acquisition keeps probing the lock for up to `probe` to measure by how
+    /// much it missed, so the error is returned up to `probe` later. A zero `probe` only counts
+    /// timeouts.
+    pub fn timeout_stats(mut self, probe: Duration) -> Self {
+        self.timeout_probe = Some(probe);
+        self
+    }
+
+    /// Spaces the attempts of every retry loop on the lock with a backoff from `new_backoff`.
+    ///
+    /// Covers timed acquisitions, their timeout probe, [`RwLock::retry_write`], and
+    /// [`try_acquire_any`](super::try_acquire_any) when the lock comes first in the list, instead
+    /// of the default [`ExponentialBackoff`](super::ExponentialBackoff). `new_backoff` is called
+    /// once per loop, so the backoff can keep state across the attempts of a single loop.
+    pub fn backoff<B, F>(mut self, new_backoff: F) -> Self
+    where
+        B: Backoff + 'static,
+        F: Fn() -> B + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    {
+        self.backoff = Some(NewBackoff(Arc::new(move || Box::new(new_backoff()))));
+        self
+    }
+
+    /// Preallocates room for `capacity` acquisition sites in the hold profile, see
+    /// [`RwLock::hold_profile`].
+    #[cfg(feature = "profile")]
+    pub fn profile_capacity(mut self, capacity: usize) -> Self {
+        self.profile_capacity = capacity;
+        self
+    }
+
+    /// Counts acquisitions per thread, see [`RwLock::fairness_index`].
+    ///
+    /// Every acquisition then takes a mutex shared by the threads acquiring the lock, meant for
+    /// capacity planning and tests rather than hot locks in production.
+    #[cfg(feature = "fairness")]
+    pub fn fairness_metrics(mut self) -> Self {
+        self.fairness_metrics = true;
+        self
+    }
+
+    /// Creates the configured lock, or returns the first invalid option found.
+    pub fn build(self) -> Result<RwLock<T>, BuildError> {
+        if self.label == Some("") {
+            return Err(BuildError::EmptyLabel);
+        }
+        if matches!(self.hold_warn, Some((_, 0))) {
+            return Err(BuildError::ZeroSampleRate);
+        }
+        if self.deadlock_timeout == Some(Duration::ZERO) {
+            return Err(BuildError::ZeroDeadlockTimeout);
+        }
+        let snapshot = self.snapshot.map(|new_snapshot| new_snapshot(&self.value));
+        let mut lock = RwLock::new(self.value);
+        lock.snapshot = snapshot;
+        lock.label = self.label;
+        lock.hold_warn = self
+            .hold_warn
+            .map(|(threshold, sample_rate)| HoldWarn::new(threshold, sample_rate));
+        if self.priority_tracking {
+            lock.priority = Some(PriorityTracking::default());
+        }
+        lock.deadlock = self
+            .deadlock_timeout
+            .map(|timeout| DeadlockWatch::new(timeout, self.on_deadlock));
+        if self.trace_capacity > 0 {
+            lock.trace = Some(AcquisitionTrace::with_capacity(self.trace_capacity));
+        }
+        if self.site_stats_capacity > 0 {
+            lock.sites = Some(SiteCounters::with_capacity(self.site_stats_capacity));
+        }
+        lock.timeouts = self.timeout_probe.map(TimeoutCounters::new);
+        lock.backoff = self.backoff;
+        #[cfg(feature = "profile")]
+        {
+            lock.profile = super::profile::HoldProfile::with_capacity(self.profile_capacity);
+        }
+        #[cfg(feature = "fairness")]
+        if self.fairness_metrics {
+            lock.fairness = Some(super::fairness::FairnessCounters::default());
+        }
+        Ok(lock)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> RwLockBuilder<T> {
+    /// Publishes a clone of the value on every write release, see [`RwLock::read_stale`].
+    pub fn stale_snapshots(mut self) -> Self {
+        self.snapshot = Some(|value| Box::new(StaleSnapshot::new(value)));
+        self
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Returns a builder to configure the optional behaviors of a lock storing `value`.
+    pub fn builder(value: T) -> RwLockBuilder<T> {
+        RwLockBuilder {
+            value,
+            label: None,
+            hold_warn: None,
+            deadlock_timeout: None,
+            on_deadlock: None,
+            priority_tracking: false,
+            trace_capacity: 0,
+            site_stats_capacity: 0,
+            timeout_probe: None,
+            backoff: None,
+            #[cfg(feature = "profile")]
+            profile_capacity: 0,
+            #[cfg(feature = "fairness")]
+            fairness_metrics: false,
+            snapshot: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_configures_each_option() {
+        let lock = RwLock::builder(0u8)
+            .label("jobs")
+            .hold_warn_sampled(Duration::from_millis(5), 10)
+            .trace_capacity(8)
+            .build()
+            .unwrap();
+        assert_eq!(lock.label(), Some("jobs"));
+        assert!(lock.describe().starts_with("jobs "));
+        assert!(lock.hold_warn.is_some());
+        lock.read().unwrap().release();
+        assert_eq!(lock.trace().len(), 1);
+
+        let plain = RwLock::builder(0u8).build().unwrap();
+        assert_eq!(plain.label(), None);
+        assert!(plain.hold_warn.is_none());
+        assert!(plain.trace.is_none());
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_options() {
+        let zero_rate = RwLock::builder(0u8).hold_warn_sampled(Duration::from_millis(5), 0);
+        assert_eq!(zero_rate.build().unwrap_err(), BuildError::ZeroSampleRate);
+        let empty_label = RwLock::builder(0u8).label("");
+        assert_eq!(empty_label.build().unwrap_err(), BuildError::EmptyLabel);
+        let zero_timeout = RwLock::builder(0u8).deadlock_timeout(Duration::ZERO);
+        assert_eq!(
+            zero_timeout.build().unwrap_err(),
+            BuildError::ZeroDeadlockTimeout
+        );
+    }
+
+    #[cfg(feature = "profile")]
+    #[test]
+    fn test_builder_preallocates_profile() {
+        let lock = RwLock::builder(0u8).profile_capacity(32).build().unwrap();
+        assert!(lock.profile.capacity() >= 32);
+    }
+}
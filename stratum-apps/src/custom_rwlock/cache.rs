@@ -0,0 +1,130 @@
+//! Memoization of reads over rarely changing values, keyed by the lock version.
+
+use super::RwLock;
+use std::sync::{Mutex as Mutex_, PoisonError, RwLockReadGuard};
+
+/// Cached result of a [`RwLock::cached_read`], valid for one version of one lock.
+#[derive(Debug)]
+pub struct OnceCache<R> {
+    // Id and version of the lock the result was computed from.
+    entry: Mutex_<Option<(u64, u64, R)>>,
+}
+
+impl<R> OnceCache<R> {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        OnceCache {
+            entry: Mutex_::new(None),
+        }
+    }
+
+    /// Drops the cached result, if any.
+    pub fn clear(&self) {
+        *self.entry.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+impl<R> Default for OnceCache<R> {
+    fn default() -> Self {
+        OnceCache::new()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Returns the result of `f` over the value, memoized in `cache`.
+    ///
+    /// If `cache` holds a result computed from this lock at its current [`RwLock::version`], a
+    /// clone of it is returned without taking the lock. Otherwise `f` runs under a shared lock
+    /// and its result replaces the cached one. `f` must be a pure function of the value for the
+    /// cached result to stay meaningful, and writes made through [`RwLock::raw_write`], which do
+    /// not bump the version, are not noticed.
+    #[track_caller]
+    pub fn cached_read<R, F>(
+        &self,
+        cache: &OnceCache<R>,
+        f: F,
+    ) -> Result<R, PoisonError<RwLockReadGuard<'_, T>>>
+    where
+        R: Clone,
+        F: FnOnce(&T) -> R,
+    {
+        let (id, version) = (self.id, self.version());
+        if let Some((cached_id, cached_version, result)) =
+            &*cache.entry.lock().unwrap_or_else(PoisonError::into_inner)
+        {
+            if (*cached_id, *cached_version) == (id, version) {
+                return Ok(result.clone());
+            }
+        }
+        // Writers bump the version while holding the lock, so reading it under the shared lock
+        // gives exactly the version `f` sees.
+        let (version, result) = self.safe_read(|value| (self.version(), f(value)))?;
+        *cache.entry.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some((id, version, result.clone()));
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::Cell,
+        panic::{catch_unwind, AssertUnwindSafe},
+    };
+
+    #[test]
+    fn test_cached_read_recomputes_only_after_writes() {
+        let lock = RwLock::new(vec![1u64, 2, 3]);
+        let cache = OnceCache::new();
+        let runs = Cell::new(0);
+        let sum = |values: &Vec<u64>| {
+            runs.set(runs.get() + 1);
+            values.iter().sum::<u64>()
+        };
+
+        assert_eq!(lock.cached_read(&cache, sum).unwrap(), 6);
+        assert_eq!(lock.cached_read(&cache, sum).unwrap(), 6);
+        assert_eq!(runs.get(), 1);
+
+        lock.super_safe_write(|values| values.push(4));
+        assert_eq!(lock.cached_read(&cache, sum).unwrap(), 10);
+        assert_eq!(runs.get(), 2);
+
+        // A cache filled from another lock is never served.
+        let other = RwLock::new(vec![1u64, 2, 3, 4]);
+        assert_eq!(other.cached_read(&cache, sum).unwrap(), 10);
+        assert_eq!(runs.get(), 3);
+
+        // Nor is one filled from a dropped lock, whose address and version a new lock reuses.
+        let mut slot = RwLock::new(vec![1u64]);
+        assert_eq!(slot.cached_read(&cache, sum).unwrap(), 1);
+        let addr = slot.addr();
+        slot = RwLock::new(vec![2u64]);
+        assert_eq!((slot.addr(), slot.version()), (addr, 0));
+        assert_eq!(slot.cached_read(&cache, sum).unwrap(), 2);
+        assert_eq!(runs.get(), 5);
+    }
+
+    #[test]
+    fn test_cached_read_sees_repairs_after_unpoison() {
+        let lock = RwLock::new(vec![1u64, 2]);
+        let cache = OnceCache::new();
+        let sum = |values: &Vec<u64>| values.iter().sum::<u64>();
+        assert_eq!(lock.cached_read(&cache, sum).unwrap(), 3);
+
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            lock.super_safe_write(|values| {
+                values.push(100);
+                panic!("half written");
+            })
+        }));
+        // Repair through the std guard, as the poison error hands it over.
+        let mut repaired = lock.inner.write().unwrap_or_else(PoisonError::into_inner);
+        repaired.pop();
+        repaired.push(7);
+        drop(repaired);
+        lock.unpoison();
+        assert_eq!(lock.cached_read(&cache, sum).unwrap(), 10);
+    }
+}
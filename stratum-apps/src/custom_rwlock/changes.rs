@@ -0,0 +1,91 @@
+//! Change notifications for async consumers, available with the `async` feature, see
+//! [`RwLock::changes`].
+//!
+//! The notification channel is a [`tokio::sync::watch`] channel created by the first call to
+//! [`RwLock::changes`]. Until then, write releases only check that it does not exist yet, so
+//! locks nobody subscribes to pay nothing more.
+
+use super::RwLock;
+use futures::Stream;
+use std::panic::AssertUnwindSafe;
+use tokio::sync::watch;
+
+// Notifies the subscribers of a lock on every write release.
+//
+// Unwind safe like the rest of the lock: sending only bumps the version of the channel, which a
+// panic cannot leave half done.
+pub(crate) type Changes = AssertUnwindSafe<watch::Sender<()>>;
+
+impl<T: ?Sized> RwLock<T> {
+    /// Returns a stream yielding an item after every write to the lock, for async consumers to
+    /// read the latest value.
+    ///
+    /// Notifications follow [`watch`] semantics: a consumer is told that the value changed
+    /// since it last looked, not how many times, so rapid writes may be coalesced into a single
+    /// item. Only writes made after this call are notified, and the stream ends once the lock is
+    /// dropped, or split with [`RwLock::into_parts`]. Writes made through [`RwLock::get_mut`] or
+    /// [`RwLock::raw_write`] are not notified.
+    ///
+    /// Subscribers are notified right before the write lock is released, so reading the value
+    /// upon the notification may briefly wait for the release.
+    pub fn changes(&self) -> impl Stream<Item = ()> + Send + 'static {
+        let changes = self
+            .changes
+            .get_or_init(|| AssertUnwindSafe(watch::channel(()).0))
+            .subscribe();
+        futures::stream::unfold(changes, |mut changes| async move {
+            changes.changed().await.ok()?;
+            Some(((), changes))
+        })
+    }
+
+    // Called with the write lock held, see `finish_write`.
+    pub(crate) fn notify_changes(&self) {
+        if let Some(changes) = self.changes.get() {
+            changes.send_replace(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::{sync::Arc, thread, time::Duration};
+
+    #[tokio::test]
+    async fn test_changes_wake_the_consumer() {
+        const WRITES: u32 = 10;
+        let lock = RwLock::new_shared(0u32);
+        let mut changes = std::pin::pin!(lock.changes());
+        let writer = {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || {
+                for _ in 0..WRITES {
+                    lock.super_safe_write(|v| *v += 1);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+        let mut notifications = 0;
+        let latest = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(()) = changes.next().await {
+                notifications += 1;
+                let value = lock.super_safe_read(|v| *v);
+                if value == WRITES {
+                    return value;
+                }
+            }
+            unreachable!("the lock is still alive");
+        })
+        .await
+        .unwrap();
+        writer.join().unwrap();
+        assert_eq!(latest, WRITES);
+        assert!((1..=WRITES).contains(&notifications));
+
+        // Dropping the lock ends the stream.
+        drop(lock);
+        assert_eq!(changes.next().await, None);
+    }
+}
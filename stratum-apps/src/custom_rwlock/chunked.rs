@@ -0,0 +1,100 @@
+//! Streaming of large buffers stored as `RwLock<Vec<T>>`, one shared acquisition per chunk.
+//!
+//! Holding the shared lock for the whole transfer of a large buffer keeps writers waiting for
+//! as long as the transfer takes. [`RwLock::read_chunked`] releases the lock between chunks, so
+//! the hold time is bounded by the time taken to handle a single chunk.
+
+use super::RwLock;
+use std::{ops::ControlFlow, sync::PoisonError};
+
+impl<T> RwLock<Vec<T>> {
+    /// Passes the buffer to `f` in consecutive slices of `chunk_size` elements, the last one
+    /// possibly shorter, acquiring the shared lock anew for every slice.
+    ///
+    /// Writers can acquire the lock between two chunks, so the buffer may change while it is
+    /// streamed: the chunks are taken at increasing offsets of whatever the buffer is by then,
+    /// and streaming stops early if it shrank below the next offset. Returns whether no write
+    /// happened in between, as told by [`RwLock::version`], for callers that need a consistent
+    /// view and would rather start over. `f` can stop the stream with [`ControlFlow::Break`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    #[track_caller]
+    pub fn read_chunked<F>(&self, chunk_size: usize, mut f: F) -> Result<bool, PoisonError<()>>
+    where
+        F: FnMut(&[T]) -> ControlFlow<()>,
+    {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        let mut offset = 0;
+        let mut first_version = None;
+        let mut unchanged = true;
+        loop {
+            let flow = self
+                .safe_read(|items| {
+                    let version = self.version();
+                    unchanged &= *first_version.get_or_insert(version) == version;
+                    let chunk = items.get(offset..)?;
+                    let chunk = &chunk[..chunk.len().min(chunk_size)];
+                    if chunk.is_empty() {
+                        return None;
+                    }
+                    offset += chunk.len();
+                    Some(f(chunk))
+                })
+                .map_err(|_| PoisonError::new(()))?;
+            match flow {
+                Some(ControlFlow::Continue(())) => {}
+                Some(ControlFlow::Break(())) | None => return Ok(unchanged),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread, time::Duration};
+
+    #[test]
+    fn test_read_chunked_lets_writers_interleave() {
+        let lock = RwLock::new((0..=255u8).collect::<Vec<_>>());
+        let mut streamed = Vec::new();
+        let unchanged = lock
+            .read_chunked(100, |chunk| {
+                streamed.extend_from_slice(chunk);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert!(unchanged);
+        assert_eq!(streamed, lock.super_safe_read(Vec::clone));
+
+        // A writer waiting for the lock gets it between two chunks.
+        let (started, start) = mpsc::channel();
+        let mut streamed = 0;
+        let unchanged = thread::scope(|s| {
+            let lock = &lock;
+            s.spawn(move || {
+                start.recv().unwrap();
+                lock.super_safe_write(|buffer| buffer.extend([0; 44]));
+            });
+            lock.read_chunked(10, |chunk| {
+                let _ = started.send(());
+                streamed += chunk.len();
+                thread::sleep(Duration::from_millis(2));
+                ControlFlow::Continue(())
+            })
+            .unwrap()
+        });
+        assert!(!unchanged);
+        assert_eq!(streamed, 300);
+
+        let mut chunks = 0;
+        lock.read_chunked(10, |_| {
+            chunks += 1;
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(chunks, 1);
+    }
+}
@@ -0,0 +1,62 @@
+//! Ordering of locks by their protected values.
+//!
+//! `PartialOrd`/`Ord` cannot be implemented for [`RwLock`]: comparing requires locking, and a
+//! trait impl would have no way to report a poisoned lock. [`RwLock::compare`] is the fallible
+//! equivalent.
+
+use super::RwLock;
+use std::{cmp::Ordering, sync::PoisonError};
+
+impl<T: Ord + ?Sized> RwLock<T> {
+    /// Compares the values protected by two locks.
+    ///
+    /// Comparing a lock with itself returns [`Ordering::Equal`] without locking, so it can never
+    /// deadlock behind a waiting writer. Otherwise both locks are read-locked in address order,
+    /// like [`with_all_read`](super::with_all_read).
+    ///
+    /// ```
+    /// use stratum_apps::custom_rwlock::RwLock;
+    ///
+    /// let mut priorities: Vec<RwLock<u8>> = [3, 1, 2].into_iter().map(RwLock::new).collect();
+    /// priorities.sort_by(|a, b| a.compare(b).unwrap());
+    /// let sorted: Vec<u8> = priorities.into_iter().map(RwLock::into_inner).collect();
+    /// assert_eq!(sorted, vec![1, 2, 3]);
+    /// ```
+    #[track_caller]
+    pub fn compare(&self, other: &Self) -> Result<Ordering, PoisonError<()>> {
+        if self.addr() == other.addr() {
+            return Ok(Ordering::Equal);
+        }
+        let (first, second, swapped) = if self.addr() < other.addr() {
+            (self, other, false)
+        } else {
+            (other, self, true)
+        };
+        let ordering = first
+            .safe_read(|a| second.safe_read(|b| a.cmp(b)))
+            .map_err(|_| PoisonError::new(()))?
+            .map_err(|_| PoisonError::new(()))?;
+        Ok(if swapped {
+            ordering.reverse()
+        } else {
+            ordering
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_with_itself_does_not_lock() {
+        let lock = RwLock::new(1u8);
+        let writer = lock.write().unwrap();
+        assert_eq!(lock.compare(&lock).unwrap(), Ordering::Equal);
+        writer.release();
+
+        let other = RwLock::new(2u8);
+        assert_eq!(lock.compare(&other).unwrap(), Ordering::Less);
+        assert_eq!(other.compare(&lock).unwrap(), Ordering::Greater);
+    }
+}
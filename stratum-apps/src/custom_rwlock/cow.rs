@@ -0,0 +1,144 @@
+//! Copy-on-write updates for read-mostly values stored as `RwLock<Arc<T>>`.
+//!
+//! Readers clone the `Arc` under a brief shared lock and then work on their snapshot without
+//! holding the lock at all. [`RwLock::cow_update`] keeps writers from holding the exclusive lock
+//! while they compute the new value, so readers are only ever kept waiting for a pointer swap.
+//! [`RwLock::load_inner`] and [`RwLock::store_inner`] cover the plain snapshot and swap.
+
+use super::{AutoWriteGuard, RwLock};
+use std::sync::{Arc, PoisonError, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+impl<T: ?Sized> RwLock<Arc<T>> {
+    /// Returns a snapshot of the current value, cloning the `Arc` under a shared lock.
+    #[track_caller]
+    pub fn load_inner(&self) -> Result<Arc<T>, PoisonError<RwLockReadGuard<'_, Arc<T>>>> {
+        self.safe_read(Arc::clone)
+    }
+
+    /// Replaces the value with `value`, returning the previous one.
+    ///
+    /// Only the pointer is swapped under the exclusive lock, the previous value is dropped by the
+    /// caller, outside of the lock, unless snapshots of it are still alive.
+    #[track_caller]
+    pub fn store_inner(
+        &self,
+        value: Arc<T>,
+    ) -> Result<Arc<T>, PoisonError<RwLockWriteGuard<'_, Arc<T>>>> {
+        self.safe_write(|current| std::mem::replace(current, value))
+    }
+}
+
+impl<T: Clone> RwLock<Arc<T>> {
+    /// Updates the value with `f`, copy-on-write style.
+    ///
+    /// If the exclusive lock is immediately available, `f` runs in place (cloning the value only
+    /// if readers still hold snapshots of it, like [`Arc::make_mut`]). Otherwise the current
+    /// value is cloned and `f` runs on the clone without holding the lock. The clone is then
+    /// swapped in, provided no other writer replaced the value meanwhile. If one did, the update
+    /// is retried against the newer value, which is why `f` may be called more than once.
+    ///
+    /// Snapshots taken before the update keep seeing the old value, the update is visible to
+    /// every snapshot taken after this returns.
+    #[track_caller]
+    pub fn cow_update<F>(&self, mut f: F) -> Result<(), PoisonError<()>>
+    where
+        F: FnMut(&mut T),
+    {
+        self.debug_assert_writable();
+        match self.inner.try_write() {
+            Ok(current) => {
+                let mut current = AutoWriteGuard::new(self, current);
+                f(Arc::make_mut(&mut current));
+                return Ok(());
+            }
+            Err(TryLockError::Poisoned(_)) => return Err(PoisonError::new(())),
+            Err(TryLockError::WouldBlock) => {}
+        }
+        loop {
+            let current = self
+                .safe_read(Arc::clone)
+                .map_err(|_| PoisonError::new(()))?;
+            let mut next = T::clone(&current);
+            f(&mut next);
+            let mut next = Some(next);
+            let swapped = self
+                .safe_write(|value| {
+                    if Arc::ptr_eq(value, &current) {
+                        *value = Arc::new(next.take().expect("swapped at most once"));
+                        true
+                    } else {
+                        false
+                    }
+                })
+                .map_err(|_| PoisonError::new(()))?;
+            if swapped {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Barrier, thread, time::Duration};
+
+    #[test]
+    fn test_load_returns_the_last_stored_pointer() {
+        let lock = RwLock::new_shared(Arc::new(String::from("v1")));
+        let v1 = lock.load_inner().unwrap();
+        let v2 = Arc::new(String::from("v2"));
+        let previous = lock.store_inner(v2.clone()).unwrap();
+        assert!(Arc::ptr_eq(&previous, &v1));
+        assert!(Arc::ptr_eq(&lock.load_inner().unwrap(), &v2));
+        // Snapshots outlive the swap.
+        assert_eq!(*v1, "v1");
+        drop(previous);
+        assert_eq!(Arc::strong_count(&v1), 1);
+    }
+
+    #[test]
+    fn test_cow_update_in_place() {
+        let lock = RwLock::new(Arc::new(vec![1u32]));
+        let before = lock.super_safe_read(Arc::clone);
+        lock.cow_update(|v| v.push(2)).unwrap();
+        // The snapshot taken before is untouched, new snapshots see the update.
+        assert_eq!(*before, vec![1]);
+        assert_eq!(*lock.super_safe_read(Arc::clone), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_cow_update_while_readers_hold_the_lock() {
+        let lock = RwLock::new(Arc::new(0u64));
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                lock.super_safe_read(|_| {
+                    barrier.wait();
+                    thread::sleep(Duration::from_millis(50));
+                });
+            });
+            barrier.wait();
+            // The lock is read-held, so the update runs on a clone; other readers keep going.
+            let reader = s.spawn(|| *lock.super_safe_read(Arc::clone));
+            lock.cow_update(|v| *v += 1).unwrap();
+            assert!(reader.join().unwrap() <= 1);
+        });
+        assert_eq!(*lock.super_safe_read(Arc::clone), 1);
+    }
+
+    #[test]
+    fn test_concurrent_cow_updates_are_not_lost() {
+        let lock = RwLock::new(Arc::new(0u64));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        lock.cow_update(|v| *v += 1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(*lock.super_safe_read(Arc::clone), 400);
+    }
+}
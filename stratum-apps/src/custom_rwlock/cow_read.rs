@@ -0,0 +1,120 @@
+//! Reads that decide per call whether to borrow from the lock or return an owned value.
+
+use super::{AutoReadGuard, RwLock};
+use std::{
+    borrow::{Borrow, Cow},
+    ops::Deref,
+    ptr::NonNull,
+    sync::{PoisonError, RwLockReadGuard},
+};
+
+enum Inner<'a, T: ?Sized, U: ?Sized + ToOwned> {
+    // Keeps the shared lock held for as long as the borrowed value is reachable.
+    Borrowed(AutoReadGuard<'a, T>, NonNull<U>),
+    Owned(U::Owned),
+}
+
+/// Result of [`RwLock::read_cow_with`]: either a value borrowed from the lock, which is then
+/// kept read-locked until this is dropped, or an owned value, the lock being already released.
+pub struct CowRead<'a, T: ?Sized, U: ?Sized + ToOwned> {
+    inner: Inner<'a, T, U>,
+}
+
+impl<T: ?Sized, U: ?Sized + ToOwned> CowRead<'_, T, U> {
+    /// Returns whether the value is borrowed, i.e. whether the lock is still held.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.inner, Inner::Borrowed(..))
+    }
+
+    /// Extracts the owned value, cloning it and releasing the lock if it is borrowed.
+    pub fn into_owned(self) -> U::Owned {
+        match self.inner {
+            Inner::Borrowed(guard, value) => {
+                // SAFETY: `guard` is still alive, see `Deref`.
+                let owned = unsafe { value.as_ref() }.to_owned();
+                drop(guard);
+                owned
+            }
+            Inner::Owned(owned) => owned,
+        }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized + ToOwned> Deref for CowRead<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        match &self.inner {
+            // SAFETY: the pointer was derived from the value protected by the lock, which `guard`
+            // keeps read-locked, and the value does not move while it is locked.
+            Inner::Borrowed(_guard, value) => unsafe { value.as_ref() },
+            Inner::Owned(owned) => owned.borrow(),
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Runs `f` under a shared lock and lets it choose between borrowing and owning its result.
+    ///
+    /// If `f` returns [`Cow::Borrowed`], the lock stays read-locked until the returned
+    /// [`CowRead`] is dropped, so no clone is made. If it returns [`Cow::Owned`], the lock is
+    /// released before this returns. This suits read paths that only sometimes need to build a
+    /// new value, e.g. normalizing a string that is usually already normalized.
+    #[track_caller]
+    pub fn read_cow_with<U, F>(
+        &self,
+        f: F,
+    ) -> Result<CowRead<'_, T, U>, PoisonError<RwLockReadGuard<'_, T>>>
+    where
+        U: ?Sized + ToOwned,
+        F: FnOnce(&T) -> Cow<'_, U>,
+    {
+        let guard = AutoReadGuard::new(self, self.inner.read()?);
+        let borrowed = match f(&guard) {
+            Cow::Borrowed(value) => NonNull::from(value),
+            Cow::Owned(owned) => {
+                drop(guard);
+                return Ok(CowRead {
+                    inner: Inner::Owned(owned),
+                });
+            }
+        };
+        // The pointer targets the value in the lock, not the guard, so moving the guard is fine.
+        Ok(CowRead {
+            inner: Inner::Borrowed(guard, borrowed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trimmed(value: &String) -> Cow<'_, str> {
+        if value.trim() == value {
+            Cow::Borrowed(value)
+        } else {
+            Cow::Owned(value.trim().to_owned())
+        }
+    }
+
+    #[test]
+    fn test_borrowed_result_keeps_the_lock_held() {
+        let lock = RwLock::new(String::from("pool"));
+        let name = lock.read_cow_with(trimmed).unwrap();
+        assert!(name.is_borrowed());
+        assert_eq!(&*name, "pool");
+        assert!(lock.inner.try_write().is_err());
+        assert_eq!(name.into_owned(), "pool");
+        assert!(lock.inner.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_owned_result_releases_the_lock() {
+        let lock = RwLock::new(String::from(" pool "));
+        let name = lock.read_cow_with(trimmed).unwrap();
+        assert!(!name.is_borrowed());
+        assert!(lock.inner.try_write().is_ok());
+        assert_eq!(&*name, "pool");
+    }
+}
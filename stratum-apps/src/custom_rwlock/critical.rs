@@ -0,0 +1,110 @@
+//! Read-write lock for bare-metal targets, available with the `critical-section` feature.
+//!
+//! [`CriticalSectionRwLock`] only relies on the [`critical_section`] crate and `core`, so it works
+//! on single-core embedded targets without an OS. Reads and writes both enter a critical section:
+//! there is no reader concurrency, which costs nothing on a single core, where only one context
+//! runs at a time anyway.
+//!
+//! A panic inside a critical section cannot be observed afterwards on these targets, so there is
+//! no poisoning and the accessors return the closure's result directly.
+
+use core::cell::RefCell;
+use critical_section::Mutex;
+
+/// Read-write lock backed by critical sections, with the closure API of [`super::RwLock`].
+///
+/// Nesting a read inside a read of the same lock is fine. Nesting a write with any other access
+/// to the same lock panics, as it would alias the mutable reference.
+pub struct CriticalSectionRwLock<T> {
+    value: Mutex<RefCell<T>>,
+}
+
+impl<T> CriticalSectionRwLock<T> {
+    /// Creates a new [`CriticalSectionRwLock`], storing the initial value inside.
+    ///
+    /// `const`, so the lock can be a `static`, the usual way of sharing state with interrupt
+    /// handlers.
+    pub const fn new(value: T) -> Self {
+        CriticalSectionRwLock {
+            value: Mutex::new(RefCell::new(value)),
+        }
+    }
+
+    /// Consumes the lock and returns the inner value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner().into_inner()
+    }
+
+    /// Returns a mutable reference to the inner value.
+    ///
+    /// No critical section is needed since the mutable borrow guarantees exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut().get_mut()
+    }
+
+    /// Runs `thunk` with a reference to the inner value, inside a critical section.
+    pub fn safe_read<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        critical_section::with(|cs| thunk(&self.value.borrow(cs).borrow()))
+    }
+
+    /// Runs `thunk` with a mutable reference to the inner value, inside a critical section.
+    pub fn safe_write<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        critical_section::with(|cs| thunk(&mut self.value.borrow(cs).borrow_mut()))
+    }
+
+    /// Same as [`CriticalSectionRwLock::safe_read`], for code written against
+    /// [`super::RwLock::super_safe_read`].
+    pub fn super_safe_read<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        self.safe_read(thunk)
+    }
+
+    /// Same as [`CriticalSectionRwLock::safe_write`], for code written against
+    /// [`super::RwLock::super_safe_write`].
+    pub fn super_safe_write<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        self.safe_write(thunk)
+    }
+}
+
+impl<T: Default> Default for CriticalSectionRwLock<T> {
+    fn default() -> Self {
+        CriticalSectionRwLock::new(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static COUNTER: CriticalSectionRwLock<u32> = CriticalSectionRwLock::new(0);
+
+    #[test]
+    fn test_read_and_write_in_critical_sections() {
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        COUNTER.safe_write(|v| *v += 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(COUNTER.safe_read(|v| *v), 4000);
+        // Nested reads of the same lock share the borrow.
+        assert_eq!(
+            COUNTER.safe_read(|a| COUNTER.super_safe_read(|b| a + b)),
+            8000
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Helpers for trait objects stored behind a [`RwLock`].
+
+use super::RwLock;
+use std::sync::{PoisonError, RwLockReadGuard, RwLockWriteGuard};
+
+/// Access to a boxed trait object stored as `RwLock<Box<dyn Trait>>`.
+///
+/// Hides the double indirection of the lock guard and the box, so closures directly get a
+/// `&dyn Trait` or `&mut dyn Trait`.
+///
+/// ```
+/// use stratum_apps::custom_rwlock::{DynRwLockExt, RwLock};
+///
+/// trait Handler {
+///     fn name(&self) -> String;
+///     fn rename(&mut self, name: &str);
+/// }
+///
+/// struct Plugin(String);
+///
+/// impl Handler for Plugin {
+///     fn name(&self) -> String {
+///         self.0.clone()
+///     }
+///
+///     fn rename(&mut self, name: &str) {
+///         self.0 = name.to_string();
+///     }
+/// }
+///
+/// let handler: RwLock<Box<dyn Handler>> = RwLock::new(Box::new(Plugin("old".into())));
+/// handler.write_dyn(|h| h.rename("new")).unwrap();
+/// assert_eq!(handler.with_dyn(|h| h.name()).unwrap(), "new");
+/// ```
+pub trait DynRwLockExt<U: ?Sized> {
+    /// Runs `f` with a shared reference to the trait object, under a shared lock.
+    #[allow(clippy::type_complexity)]
+    fn with_dyn<F, Ret>(&self, f: F) -> Result<Ret, PoisonError<RwLockReadGuard<'_, Box<U>>>>
+    where
+        F: FnOnce(&U) -> Ret;
+
+    /// Runs `f` with a mutable reference to the trait object, under an exclusive lock.
+    #[allow(clippy::type_complexity)]
+    fn write_dyn<F, Ret>(&self, f: F) -> Result<Ret, PoisonError<RwLockWriteGuard<'_, Box<U>>>>
+    where
+        F: FnOnce(&mut U) -> Ret;
+}
+
+impl<U: ?Sized> DynRwLockExt<U> for RwLock<Box<U>> {
+    #[track_caller]
+    fn with_dyn<F, Ret>(&self, f: F) -> Result<Ret, PoisonError<RwLockReadGuard<'_, Box<U>>>>
+    where
+        F: FnOnce(&U) -> Ret,
+    {
+        self.safe_read(|boxed| f(&**boxed))
+    }
+
+    #[track_caller]
+    fn write_dyn<F, Ret>(&self, f: F) -> Result<Ret, PoisonError<RwLockWriteGuard<'_, Box<U>>>>
+    where
+        F: FnOnce(&mut U) -> Ret,
+    {
+        self.safe_write(|boxed| f(&mut **boxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dyn_access_is_attributed_to_the_caller() {
+        let lock = RwLock::builder(Box::new(1u32) as Box<dyn Send + Sync>)
+            .site_stats_capacity(4)
+            .build()
+            .unwrap();
+        let lines = [line!() + 1, line!() + 2];
+        lock.with_dyn(|_| ()).unwrap();
+        lock.write_dyn(|_| ()).unwrap();
+        let mut sites: Vec<u32> = lock
+            .site_stats()
+            .iter()
+            .map(|(site, _)| site.line())
+            .collect();
+        sites.sort_unstable();
+        assert_eq!(sites, lines);
+    }
+}
@@ -0,0 +1,212 @@
+//! Lock-free reads over epoch-reclaimed versions, available with the `epoch` feature.
+//!
+//! [`EpochRwLock`] keeps the current version of the value behind an atomic pointer. Readers pin
+//! an epoch with [`crossbeam_epoch`] and dereference the pointer without taking any lock, so
+//! they never wait, not even for a writer. Writers build a new version from the current one and
+//! swap it in, and the old version is only destroyed once every reader pinned before the swap
+//! has unpinned.
+
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned};
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::Deref,
+    sync::{atomic::Ordering, Mutex as Mutex_, PoisonError},
+};
+
+/// Read-write lock with lock-free reads, for read-dominated workloads on values that are cheap
+/// enough to rebuild on every write.
+///
+/// Writing requires `T: Send + 'static`: a replaced version is destroyed by whichever thread
+/// happens to collect it, at some point after every reader let go of it, possibly once the lock
+/// itself is gone.
+pub struct EpochRwLock<T> {
+    current: Atomic<T>,
+    // Serializes writers, so no update is lost between loading a version and swapping it out.
+    writer: Mutex_<()>,
+}
+
+/// Shared access to one version of the value of an [`EpochRwLock`].
+///
+/// Pins an epoch for as long as it lives, which keeps the version it points to from being
+/// reclaimed. Later writes are not visible through it. Like the epoch guard it wraps it cannot
+/// be sent to another thread, and should not be kept for long, as it delays the reclamation of
+/// every version replaced meanwhile.
+pub struct EpochReadGuard<'a, T> {
+    _pinned: Guard,
+    value: *const T,
+    _lock: PhantomData<&'a EpochRwLock<T>>,
+}
+
+impl<T> Deref for EpochReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the version was loaded under `_pinned`, which defers its destruction until
+        // this guard is dropped.
+        unsafe { &*self.value }
+    }
+}
+
+impl<T> EpochRwLock<T> {
+    /// Creates a new [`EpochRwLock`], storing the initial value inside.
+    pub fn new(value: T) -> Self {
+        EpochRwLock {
+            current: Atomic::new(value),
+            writer: Mutex_::new(()),
+        }
+    }
+
+    /// Returns the current version of the value, without blocking.
+    pub fn read(&self) -> EpochReadGuard<'_, T> {
+        let pinned = epoch::pin();
+        let value = self.current.load(Ordering::Acquire, &pinned).as_raw();
+        EpochReadGuard {
+            _pinned: pinned,
+            value,
+            _lock: PhantomData,
+        }
+    }
+
+    /// Runs `thunk` with a reference to the current version of the value, without blocking.
+    pub fn safe_read<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        thunk(&self.read())
+    }
+
+    /// Consumes the lock and returns the inner value.
+    pub fn into_inner(self) -> T {
+        let this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: owning the lock means no reader is left, so the current version can be taken
+        // without pinning. `this` is not dropped, so it is not destroyed twice.
+        let value = unsafe {
+            this.current
+                .load(Ordering::Relaxed, epoch::unprotected())
+                .into_owned()
+        };
+        *value.into_box()
+    }
+}
+
+impl<T: Send + 'static> EpochRwLock<T> {
+    /// Replaces the value with the new version built by `f` from the current one.
+    ///
+    /// Writers are serialized, so every update sees the result of the previous one. Readers keep
+    /// going meanwhile, on the current version until the new one is swapped in. If `f` panics
+    /// the value is left unchanged.
+    pub fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&T) -> T,
+    {
+        // Only serializes writers, nothing it protects can be left half updated.
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let pinned = epoch::pin();
+        // SAFETY: the pointer is never null and the version is pinned.
+        let next = f(unsafe { self.current.load(Ordering::Acquire, &pinned).deref() });
+        self.swap(next, &pinned);
+    }
+
+    /// Replaces the value with `value`.
+    pub fn store(&self, value: T) {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        self.swap(value, &epoch::pin());
+    }
+
+    fn swap(&self, value: T, pinned: &Guard) {
+        let old = self
+            .current
+            .swap(Owned::new(value), Ordering::AcqRel, pinned);
+        // SAFETY: the old version is unreachable from now on, and readers that loaded it before
+        // the swap are pinned, which defers the destruction until they are gone. `T: Send +
+        // 'static` lets any thread destroy it at any later point.
+        unsafe { pinned.defer_destroy(old) };
+    }
+}
+
+impl<T> Drop for EpochRwLock<T> {
+    fn drop(&mut self) {
+        // SAFETY: see `into_inner`.
+        unsafe {
+            drop(
+                self.current
+                    .load(Ordering::Relaxed, epoch::unprotected())
+                    .into_owned(),
+            );
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for EpochRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EpochRwLock")
+            .field("current", &*self.read())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    struct Tracked(u64, Arc<AtomicUsize>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_reads_never_wait_for_writers() {
+        let lock = EpochRwLock::new(0u64);
+        lock.update(|v| {
+            // A reader gets through while the writer is still building the new version.
+            thread::scope(|s| s.spawn(|| *lock.read()).join().unwrap()) + v + 1
+        });
+        assert_eq!(*lock.read(), 1);
+
+        let reader = lock.read();
+        lock.store(2);
+        // The pinned version is unaffected by the write.
+        assert_eq!((*reader, *lock.read()), (1, 2));
+    }
+
+    #[test]
+    fn test_old_versions_are_reclaimed() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let lock = EpochRwLock::new(Tracked(0, dropped.clone()));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        assert!(lock.read().0 <= 100);
+                    }
+                });
+            }
+            for _ in 0..100 {
+                lock.update(|v| Tracked(v.0 + 1, v.1.clone()));
+            }
+        });
+        assert_eq!(lock.read().0, 100);
+        for _ in 0..1000 {
+            if dropped.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            epoch::pin().flush();
+        }
+        assert!(dropped.load(Ordering::SeqCst) > 0);
+
+        let before = dropped.load(Ordering::SeqCst);
+        drop(lock);
+        assert_eq!(dropped.load(Ordering::SeqCst), before + 1);
+    }
+}
@@ -0,0 +1,356 @@
+//! A read-write lock granting access in strict request order.
+//!
+//! The fairness of [`std::sync::RwLock`] is platform dependent. [`FairRwLock`] instead keeps an
+//! explicit queue of waiters: every acquirer takes a ticket, and only the head of the queue may
+//! acquire the lock. Consecutive readers at the head are admitted together, a writer waits for
+//! every reader admitted before it, and nobody overtakes a waiter that queued earlier.
+//!
+//! [`with_all_fair_write`] acquires several fair locks at once, in address order like
+//! [`with_all_write`](super::with_all_write), to combine starvation freedom with deadlock freedom.
+
+use std::{
+    cell::UnsafeCell,
+    collections::VecDeque,
+    fmt,
+    sync::{Condvar, Mutex as Mutex_, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct State {
+    // Tickets of the waiters, in request order, with whether they want to write.
+    queue: VecDeque<(u64, bool)>,
+    next_ticket: u64,
+    readers: usize,
+    writer: bool,
+}
+
+/// Read-write lock granting access in FIFO order, whatever the platform.
+///
+/// Access is closure-based like [`RwLock::safe_read`](super::RwLock::safe_read) and
+/// [`RwLock::safe_write`](super::RwLock::safe_write). A panicking closure releases the lock but
+/// does not poison it.
+pub struct FairRwLock<T: ?Sized> {
+    state: Mutex_<State>,
+    // Notified whenever the lock is released or the head of the queue changes.
+    changed: Condvar,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the value is only reached through `&T` by concurrent readers or `&mut T` by a single
+// writer, exactly as for `std::sync::RwLock`, hence the same bounds.
+unsafe impl<T: ?Sized + Send> Send for FairRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for FairRwLock<T> {}
+
+// Releases the lock when dropped, including while unwinding out of the closure.
+struct Release<'a, T: ?Sized> {
+    lock: &'a FairRwLock<T>,
+    write: bool,
+}
+
+impl<T: ?Sized> Drop for Release<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state();
+        if self.write {
+            state.writer = false;
+        } else {
+            state.readers -= 1;
+        }
+        drop(state);
+        self.lock.changed.notify_all();
+    }
+}
+
+impl<T> FairRwLock<T> {
+    /// Creates a new [`FairRwLock`], storing the initial value inside.
+    pub fn new(value: T) -> Self {
+        FairRwLock {
+            state: Mutex_::new(State::default()),
+            changed: Condvar::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the lock and returns the inner value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> FairRwLock<T> {
+    /// Runs `thunk` with shared access, once every waiter that queued earlier got its turn.
+    pub fn safe_read<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        let _release = self.acquire(false, None).expect("waits without a deadline");
+        // SAFETY: a shared acquisition excludes writers until `_release` is dropped.
+        thunk(unsafe { &*self.value.get() })
+    }
+
+    /// Runs `thunk` with exclusive access, once every waiter that queued earlier got its turn.
+    pub fn safe_write<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        let _release = self.acquire(true, None).expect("waits without a deadline");
+        // SAFETY: an exclusive acquisition excludes everybody else until `_release` is dropped.
+        thunk(unsafe { &mut *self.value.get() })
+    }
+
+    /// Same as [`FairRwLock::safe_read`], giving up after `timeout`.
+    ///
+    /// A waiter that gives up leaves the queue, so it never holds back the ones behind it. A
+    /// timeout too large to hold a deadline, such as [`Duration::MAX`], never gives up.
+    pub fn try_read_for<F, Ret>(&self, timeout: Duration, thunk: F) -> Option<Ret>
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        let _release = self.acquire(false, Instant::now().checked_add(timeout))?;
+        // SAFETY: see `safe_read`.
+        Some(thunk(unsafe { &*self.value.get() }))
+    }
+
+    /// Same as [`FairRwLock::safe_write`], giving up after `timeout`.
+    pub fn try_write_for<F, Ret>(&self, timeout: Duration, thunk: F) -> Option<Ret>
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        let _release = self.acquire(true, Instant::now().checked_add(timeout))?;
+        // SAFETY: see `safe_write`.
+        Some(thunk(unsafe { &mut *self.value.get() }))
+    }
+
+    /// Returns a mutable reference to the inner value.
+    ///
+    /// No locking is needed since the mutable borrow guarantees exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Number of acquirers currently queued.
+    pub fn waiting(&self) -> usize {
+        self.state().queue.len()
+    }
+
+    // The state is only modified in short non-panicking sections, so poisoning can be ignored.
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn acquire(&self, write: bool, deadline: Option<Instant>) -> Option<Release<'_, T>> {
+        let mut state = self.state();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.queue.push_back((ticket, write));
+        loop {
+            let at_head = state.queue.front().map(|(head, _)| *head) == Some(ticket);
+            if at_head && !state.writer && (!write || state.readers == 0) {
+                state.queue.pop_front();
+                if write {
+                    state.writer = true;
+                } else {
+                    state.readers += 1;
+                }
+                drop(state);
+                // The next waiter is now at the head: another reader may be admitted right away.
+                self.changed.notify_all();
+                return Some(Release { lock: self, write });
+            }
+            state = match deadline {
+                None => self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.queue.retain(|(queued, _)| *queued != ticket);
+                        drop(state);
+                        // Leaving the queue may have made somebody else the head.
+                        self.changed.notify_all();
+                        return None;
+                    }
+                    self.changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for FairRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("FairRwLock")
+            .field("readers", &state.readers)
+            .field("writer", &state.writer)
+            .field("waiting", &state.queue.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Acquires an exclusive lock on every lock of `locks` and runs `f` over their values.
+///
+/// The values are handed to `f` in the order of `locks`, the locks are acquired in address order
+/// and released once `f` returns, or while unwinding if `f` panics. Each acquisition queues like
+/// [`FairRwLock::safe_write`], which gives the combined guarantees, as long as every call site
+/// taking more than one fair lock goes through this function:
+/// - **Deadlock freedom:** a caller only ever waits for a lock at a higher address than the ones
+///   it holds, so no two callers can wait on each other in a cycle.
+/// - **Starvation freedom:** on every lock, waiters are admitted in request order. Holders of
+///   the highest addressed locks never wait, so by induction every holder eventually releases,
+///   and every waiter gets its turn after the ones that queued before it on that lock.
+///
+/// Holding the lower addressed locks while queued for the next one is what keeps the order
+/// deadlock free, but it also holds back the waiters of those locks meanwhile: a caller may wait
+/// behind callers that queued later on another of its locks.
+///
+/// # Panics
+///
+/// Panics if the same lock is listed twice.
+pub fn with_all_fair_write<T, F, R>(locks: &[&FairRwLock<T>], f: F) -> R
+where
+    T: ?Sized,
+    F: FnOnce(&mut [&mut T]) -> R,
+{
+    let mut order: Vec<usize> = (0..locks.len()).collect();
+    order.sort_by_key(|&i| locks[i] as *const FairRwLock<T> as *const () as usize);
+    assert!(
+        order
+            .windows(2)
+            .all(|w| !std::ptr::eq(locks[w[0]], locks[w[1]])),
+        "the same lock was listed twice"
+    );
+    let mut releases = Vec::with_capacity(locks.len());
+    for i in order {
+        releases.push(
+            locks[i]
+                .acquire(true, None)
+                .expect("waits without a deadline"),
+        );
+    }
+    // SAFETY: every lock is distinct and exclusively acquired until `releases` is dropped.
+    let mut values: Vec<&mut T> = locks
+        .iter()
+        .map(|lock| unsafe { &mut *lock.value.get() })
+        .collect();
+    let result = f(&mut values);
+    drop(releases);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn test_acquisitions_complete_in_request_order() {
+        let lock = FairRwLock::new(Vec::new());
+        thread::scope(|s| {
+            lock.safe_write(|_| {
+                for i in 0..8 {
+                    let lock = &lock;
+                    s.spawn(move || lock.safe_write(|order| order.push(i)));
+                    // Queue the next acquirer only once this one is waiting.
+                    while lock.waiting() <= i {
+                        thread::yield_now();
+                    }
+                }
+            });
+        });
+        assert_eq!(lock.into_inner(), (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_unbounded_timeouts_wait() {
+        let lock = FairRwLock::new(0u8);
+        assert_eq!(lock.try_write_for(Duration::MAX, |v| *v += 1), Some(()));
+        assert_eq!(lock.try_read_for(Duration::MAX, |v| *v), Some(1));
+    }
+
+    #[test]
+    fn test_no_lost_wakeups_under_contention() {
+        let lock = FairRwLock::new(0u64);
+        let writes = AtomicU64::new(0);
+        thread::scope(|s| {
+            for t in 0..8 {
+                let (lock, writes) = (&lock, &writes);
+                s.spawn(move || {
+                    let timeout = Duration::from_micros(50);
+                    for i in 0..500 {
+                        let wrote = match (t + i) % 4 {
+                            0 => lock.safe_write(|v| {
+                                *v += 1;
+                                true
+                            }),
+                            1 => lock.try_write_for(timeout, |v| *v += 1).is_some(),
+                            2 => lock.safe_read(|_| false),
+                            _ => lock.try_read_for(timeout, |_| false).unwrap_or(false),
+                        };
+                        if wrote {
+                            writes.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        // Every thread finished, so nobody was left waiting for a missed notification.
+        assert_eq!(lock.waiting(), 0);
+        assert_eq!(lock.into_inner(), writes.into_inner());
+    }
+
+    #[test]
+    fn test_fair_batches_neither_deadlock_nor_starve() {
+        let locks = [
+            FairRwLock::new(0u64),
+            FairRwLock::new(0),
+            FairRwLock::new(0),
+        ];
+        thread::scope(|s| {
+            for t in 0..6 {
+                let locks = &locks;
+                s.spawn(move || {
+                    for i in 0..300 {
+                        // Overlapping sets, listed in conflicting orders.
+                        let (a, b) = ((t + i) % 3, (t + i + 1) % 3);
+                        let set = if t % 2 == 0 {
+                            [&locks[a], &locks[b]]
+                        } else {
+                            [&locks[b], &locks[a]]
+                        };
+                        with_all_fair_write(&set, |values| {
+                            values.iter_mut().for_each(|v| **v += 1)
+                        });
+                    }
+                });
+            }
+        });
+        let total: u64 = locks.into_iter().map(FairRwLock::into_inner).sum();
+        assert_eq!(total, 6 * 300 * 2);
+
+        // Batches queue on each lock in request order, whichever lock comes first in address
+        // order.
+        let (held, other) = (FairRwLock::new(Vec::new()), FairRwLock::new(Vec::new()));
+        thread::scope(|s| {
+            held.safe_write(|_| {
+                for i in 0..4 {
+                    let (held, other) = (&held, &other);
+                    s.spawn(move || {
+                        with_all_fair_write(&[held, other], |values| values[0].push(i))
+                    });
+                    while held.waiting() + other.waiting() <= i {
+                        thread::yield_now();
+                    }
+                }
+            });
+        });
+        assert_eq!(held.into_inner(), (0..4).collect::<Vec<_>>());
+    }
+}
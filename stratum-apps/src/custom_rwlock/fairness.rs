@@ -0,0 +1,81 @@
+//! Per-thread acquisition counts summarized as a fairness index, available with the `fairness`
+//! feature and enabled with
+//! [`RwLockBuilder::fairness_metrics`](super::RwLockBuilder::fairness_metrics).
+//!
+//! Raw acquisition counts hide starvation: a lock acquired a million times looks healthy even if
+//! one thread took almost all of them. [`RwLock::fairness_index`] folds the counts of every thread
+//! into Jain's fairness index, `(Σx)² / (n·Σx²)`, which is 1.0 when every thread acquired the lock
+//! equally often and tends to `1/n` when a single one of `n` threads monopolizes it.
+
+use super::RwLock;
+use std::{
+    collections::HashMap,
+    sync::{Mutex as Mutex_, PoisonError},
+    thread::{self, ThreadId},
+};
+
+#[derive(Debug, Default)]
+pub(crate) struct FairnessCounters {
+    acquisitions: Mutex_<HashMap<ThreadId, u64>>,
+}
+
+impl FairnessCounters {
+    pub(crate) fn record(&self) {
+        let mut acquisitions = self
+            .acquisitions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let count = acquisitions.entry(thread::current().id()).or_default();
+        *count = count.saturating_add(1);
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Returns Jain's fairness index of the acquisitions of this lock across threads, from `1/n`
+    /// for `n` threads when one of them took every acquisition, to 1.0 when all took as many.
+    ///
+    /// Only threads that acquired the lock at least once count, so a thread starved from the
+    /// start does not show. 1.0 if the lock was built without fairness metrics or never acquired.
+    pub fn fairness_index(&self) -> f64 {
+        let Some(fairness) = &self.fairness else {
+            return 1.0;
+        };
+        let acquisitions = fairness
+            .acquisitions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (sum, sum_of_squares) = acquisitions
+            .values()
+            .map(|&count| count as f64)
+            .fold((0.0, 0.0), |(sum, squares), count| {
+                (sum + count, squares + count * count)
+            });
+        if sum_of_squares == 0.0 {
+            return 1.0;
+        }
+        sum * sum / (acquisitions.len() as f64 * sum_of_squares)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_after(acquisitions: [u32; 4]) -> f64 {
+        let lock = RwLock::builder(0u32).fairness_metrics().build().unwrap();
+        thread::scope(|s| {
+            for count in acquisitions {
+                let lock = &lock;
+                s.spawn(move || (0..count).for_each(|_| lock.super_safe_write(|v| *v += 1)));
+            }
+        });
+        lock.fairness_index()
+    }
+
+    #[test]
+    fn test_fairness_index_exposes_monopolies() {
+        assert!(index_after([100, 100, 100, 100]) > 0.99);
+        assert!(index_after([1000, 1, 1, 1]) < 0.3);
+        assert_eq!(RwLock::new(0u8).fairness_index(), 1.0);
+    }
+}
@@ -0,0 +1,150 @@
+//! Critical sections with explicit memory fences, for code mixing the lock with atomics that are
+//! accessed without it.
+//!
+//! The lock already orders everything done under it for other users of the lock. These methods
+//! additionally make the critical section usable as a synchronization point for atomics accessed
+//! *outside* the lock, with `Relaxed` operations, by issuing:
+//! - a [`fence`]`(Acquire)` right after the lock is acquired: if before the call this thread
+//!   loaded a value written by another thread after a `Release` fence (or with a `Release`
+//!   store), everything that thread did before that store is visible inside the critical section.
+//! - a [`fence`]`(Release)` right before the lock is released: everything done inside the critical
+//!   section, including `Relaxed` stores to co-located atomics, happens-before what another thread
+//!   observes through a store made by this thread after the call, as long as that thread loads
+//!   the store and then issues a `fence(Acquire)` (or loads it with `Acquire`).
+
+use super::RwLock;
+// Loom does not see the std lock, only its own atomics and fences: modeling the fences alone
+// checks that they, and not the lock, order the atomics accessed outside of it.
+#[cfg(loom)]
+use loom::sync::atomic::fence;
+#[cfg(not(loom))]
+use std::sync::atomic::fence;
+use std::sync::{atomic::Ordering, PoisonError, RwLockReadGuard, RwLockWriteGuard};
+
+impl<T: ?Sized> RwLock<T> {
+    /// Same as [`RwLock::safe_read`], with an `Acquire` fence after acquisition and a `Release`
+    /// fence before release.
+    #[track_caller]
+    pub fn read_acq_rel<F, Ret>(&self, thunk: F) -> Result<Ret, PoisonError<RwLockReadGuard<'_, T>>>
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        self.safe_read(|value| {
+            fence(Ordering::Acquire);
+            let return_value = thunk(value);
+            fence(Ordering::Release);
+            return_value
+        })
+    }
+
+    /// Same as [`RwLock::safe_write`], with an `Acquire` fence after acquisition and a `Release`
+    /// fence before release.
+    #[track_caller]
+    pub fn write_acq_rel<F, Ret>(
+        &self,
+        thunk: F,
+    ) -> Result<Ret, PoisonError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        self.safe_write(|value| {
+            fence(Ordering::Acquire);
+            let return_value = thunk(value);
+            fence(Ordering::Release);
+            return_value
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(loom))]
+    use std::{
+        sync::atomic::{AtomicBool, AtomicU64},
+        thread,
+    };
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_publication_through_relaxed_flag() {
+        for _ in 0..100 {
+            let lock = RwLock::new(0u64);
+            let data = AtomicU64::new(0);
+            let published = AtomicBool::new(false);
+            thread::scope(|s| {
+                s.spawn(|| {
+                    while !published.load(Ordering::Relaxed) {
+                        std::hint::spin_loop();
+                    }
+                    fence(Ordering::Acquire);
+                    assert_eq!(data.load(Ordering::Relaxed), 42);
+                });
+                s.spawn(|| {
+                    lock.write_acq_rel(|value| {
+                        *value = 42;
+                        data.store(*value, Ordering::Relaxed);
+                    })
+                    .unwrap();
+                    published.store(true, Ordering::Relaxed);
+                });
+            });
+            assert_eq!(lock.read_acq_rel(|value| *value).unwrap(), 42);
+        }
+    }
+
+    // Explores every interleaving and every value the relaxed loads may read: fails if either
+    // fence of `write_acq_rel` is removed.
+    #[cfg(loom)]
+    #[test]
+    fn test_loom_publication_through_relaxed_flag() {
+        use loom::sync::{
+            atomic::{AtomicBool, AtomicU64},
+            Arc,
+        };
+
+        loom::model(|| {
+            let lock = Arc::new(RwLock::new(0u64));
+            let data = Arc::new(AtomicU64::new(0));
+            let published = Arc::new(AtomicBool::new(false));
+            let writer = {
+                let (lock, data, published) = (lock.clone(), data.clone(), published.clone());
+                loom::thread::spawn(move || {
+                    lock.write_acq_rel(|value| {
+                        *value = 42;
+                        data.store(*value, Ordering::Relaxed);
+                    })
+                    .unwrap();
+                    published.store(true, Ordering::Relaxed);
+                })
+            };
+            if published.load(Ordering::Relaxed) {
+                fence(Ordering::Acquire);
+                assert_eq!(data.load(Ordering::Relaxed), 42);
+            }
+            writer.join().unwrap();
+        });
+
+        // The other way around, through the fence after acquisition.
+        loom::model(|| {
+            let lock = Arc::new(RwLock::new(()));
+            let data = Arc::new(AtomicU64::new(0));
+            let published = Arc::new(AtomicBool::new(false));
+            let writer = {
+                let (data, published) = (data.clone(), published.clone());
+                loom::thread::spawn(move || {
+                    data.store(42, Ordering::Relaxed);
+                    fence(Ordering::Release);
+                    published.store(true, Ordering::Relaxed);
+                })
+            };
+            if published.load(Ordering::Relaxed) {
+                let seen = lock
+                    .read_acq_rel(|()| data.load(Ordering::Relaxed))
+                    .unwrap();
+                assert_eq!(seen, 42);
+            }
+            writer.join().unwrap();
+        });
+    }
+}
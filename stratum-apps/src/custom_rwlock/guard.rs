@@ -0,0 +1,644 @@
+//! Guards returned by [`RwLock::read`] and [`RwLock::write`], and their auto-release
+//! counterparts returned by [`RwLock::read_auto`] and [`RwLock::write_auto`].
+//!
+//! The strict guards must be handed back with `release()`. Dropping one that was not released
+//! panics (unless the thread is already panicking), which turns a forgotten release into an
+//! immediate, well located failure. The auto guards simply release the lock when dropped.
+//!
+//! Acquiring and releasing a guard does not allocate: guards live on the caller's stack and only
+//! wrap the std guard plus the acquisition bookkeeping. That is unless a deadlock timeout or the
+//! `test-util` feature is enabled, since both record every acquisition in a map.
+
+use super::{acquisition::Acquired, holders::HeldMode, RwLock};
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{RwLockReadGuard, RwLockWriteGuard},
+};
+
+// Keeps every guard `!Send` on its own, whatever the fields around it: a guard held across an
+// `.await` then makes the future `!Send`, and spawning it on a multi-threaded runtime fails to
+// compile. `Sync` is implemented explicitly below for each guard.
+type NotSend = PhantomData<*const ()>;
+
+/// Shared access to the value protected by a [`RwLock`].
+///
+/// Must be released with [`ReadGuard::release`].
+pub struct ReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    inner: Option<(RwLockReadGuard<'a, T>, Acquired)>,
+    _not_send: NotSend,
+}
+
+impl<'a, T: ?Sized> ReadGuard<'a, T> {
+    #[track_caller]
+    pub(super) fn new(lock: &'a RwLock<T>, inner: RwLockReadGuard<'a, T>) -> Self {
+        ReadGuard {
+            lock,
+            inner: Some((inner, lock.on_acquire(HeldMode::Read))),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Releases the shared lock.
+    pub fn release(mut self) {
+        self.unlock();
+    }
+
+    // Releases the lock. Returns whether it was still held.
+    fn unlock(&mut self) -> bool {
+        unlock_read(self.lock, &mut self.inner)
+    }
+
+    /// Turns this guard into an [`AutoReadGuard`], which releases the lock when dropped.
+    pub fn into_auto(mut self) -> AutoReadGuard<'a, T> {
+        AutoReadGuard {
+            lock: self.lock,
+            inner: self.inner.take(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Borrows the protected value, leaving the guard owned by the caller.
+    ///
+    /// Same as `&*guard`, spelled out for helpers that take the value rather than the guard.
+    ///
+    /// ```
+    /// use stratum_apps::custom_rwlock::RwLock;
+    ///
+    /// fn total(values: &[u32]) -> u32 {
+    ///     values.iter().sum()
+    /// }
+    ///
+    /// let lock = RwLock::new(vec![1, 2, 3]);
+    /// let guard = lock.read().unwrap();
+    /// assert_eq!(total(guard.borrow()), 6);
+    /// guard.release();
+    /// ```
+    #[allow(clippy::should_implement_trait)]
+    pub fn borrow(&self) -> &T {
+        self
+    }
+
+    /// Keeps the guard only if `f` holds for the protected value.
+    ///
+    /// Otherwise the guard is released and `None` is returned, so the lock is free again by the
+    /// time the caller sees the result.
+    pub fn filter<F>(self, f: F) -> Option<Self>
+    where
+        F: FnOnce(&T) -> bool,
+    {
+        if f(&self) {
+            Some(self)
+        } else {
+            self.release();
+            None
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.as_ref().expect("guard is held until released").0
+    }
+}
+
+impl<T: ?Sized> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.unlock() && !std::thread::panicking() {
+            panic!(
+                "ReadGuard on {} dropped without being released",
+                self.lock.describe()
+            );
+        }
+    }
+}
+
+// SAFETY: sharing a `&ReadGuard` only gives access to `&T` (through `Deref`), so it is sound to
+// share whenever `T: Sync`, like `std::sync::RwLockReadGuard`. The auto trait would also require
+// `T: Send` because of the `&RwLock<T>` field, which is only used to describe the lock.
+// The guard stays `!Send`: the std guard it wraps must be released on the acquiring thread.
+unsafe impl<T: ?Sized + Sync> Sync for ReadGuard<'_, T> {}
+
+/// Exclusive access to the value protected by a [`RwLock`].
+///
+/// Must be released with [`WriteGuard::release`].
+pub struct WriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    inner: Option<(RwLockWriteGuard<'a, T>, Acquired)>,
+    _not_send: NotSend,
+}
+
+impl<'a, T: ?Sized> WriteGuard<'a, T> {
+    #[track_caller]
+    pub(super) fn new(lock: &'a RwLock<T>, inner: RwLockWriteGuard<'a, T>) -> Self {
+        WriteGuard {
+            lock,
+            inner: Some((inner, lock.on_acquire(HeldMode::Write))),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Releases the exclusive lock.
+    pub fn release(mut self) {
+        self.unlock();
+    }
+
+    /// Releases the exclusive lock, then runs `f`.
+    ///
+    /// Makes follow-up work that must not hold the lock explicit: `f` only runs once the lock is
+    /// released, and the guard is consumed, so the value cannot be reached from `f` through it.
+    pub fn release_and_then<R, F>(self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        self.release();
+        f()
+    }
+
+    /// Releases the exclusive lock, poisoning it if `should_poison` returns `true` for the value.
+    ///
+    /// A production safety gate for invariants: once poisoned, every acquisition of the lock
+    /// reports the poison, so nobody acts upon the known-bad value until it is repaired through
+    /// [`PoisonError::into_inner`](std::sync::PoisonError::into_inner) and
+    /// [`RwLock::unpoison`] is called.
+    ///
+    /// The write still bumps the [`RwLock::version`] and notifies the subscribers to
+    /// `RwLock::changes`, but the stale snapshot, if enabled, is not updated with a value that
+    /// poisoned the lock. Returns whether the lock was poisoned.
+    ///
+    /// Only available with `panic = "unwind"`: the std lock is only poisoned by unwinding, which
+    /// would abort the process with `panic = "abort"`.
+    #[cfg(panic = "unwind")]
+    pub fn poison_if<F>(mut self, should_poison: F) -> bool
+    where
+        F: FnOnce(&T) -> bool,
+    {
+        if !should_poison(&self) {
+            self.release();
+            return false;
+        }
+        let (inner, acquired) = self.inner.take().expect("held until released");
+        self.lock.finish_unpublished_write();
+        // The std lock is only poisoned by a guard dropped while unwinding. `resume_unwind` does
+        // not run the panic hook, so nothing gets logged.
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let _inner = inner;
+            std::panic::resume_unwind(Box::new(()));
+        }));
+        self.lock.on_release(acquired);
+        true
+    }
+
+    // Bumps the lock version and releases the lock. Returns whether it was still held.
+    fn unlock(&mut self) -> bool {
+        unlock_write(self.lock, &mut self.inner)
+    }
+
+    /// Turns this guard into an [`AutoWriteGuard`], which releases the lock when dropped.
+    pub fn into_auto(mut self) -> AutoWriteGuard<'a, T> {
+        AutoWriteGuard {
+            lock: self.lock,
+            inner: self.inner.take(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Mutably borrows the protected value, leaving the guard owned by the caller.
+    ///
+    /// Same as `&mut *guard`, spelled out for helpers that take the value rather than the guard.
+    ///
+    /// ```
+    /// use stratum_apps::custom_rwlock::RwLock;
+    ///
+    /// fn push_twice(values: &mut Vec<u32>, value: u32) {
+    ///     values.push(value);
+    ///     values.push(value);
+    /// }
+    ///
+    /// let lock = RwLock::new(Vec::new());
+    /// let mut guard = lock.write().unwrap();
+    /// push_twice(guard.borrow_mut(), 7);
+    /// assert_eq!(*guard, vec![7, 7]);
+    /// guard.release();
+    /// ```
+    #[allow(clippy::should_implement_trait)]
+    pub fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+
+    /// Borrows the protected value as shared, for the duration of the borrow of the guard.
+    ///
+    /// Lets read-only helpers be called while holding the exclusive lock, and the guard be used
+    /// mutably again once the helper returns.
+    ///
+    /// ```
+    /// use stratum_apps::custom_rwlock::RwLock;
+    ///
+    /// fn len(values: &[u32]) -> usize {
+    ///     values.len()
+    /// }
+    ///
+    /// let lock = RwLock::new(vec![1]);
+    /// let mut guard = lock.write().unwrap();
+    /// let before = len(guard.reborrow());
+    /// guard.push(2);
+    /// assert_eq!(len(guard.reborrow()), before + 1);
+    /// guard.release();
+    /// ```
+    pub fn reborrow(&self) -> &T {
+        self
+    }
+
+    /// Runs `act` on the part of the value selected by `sel`, leaving the guard owned by the
+    /// caller.
+    ///
+    /// Unlike [`WriteGuard::map_split`], the guard is only borrowed, so it can keep being used
+    /// once `act` returns.
+    ///
+    /// ```
+    /// use stratum_apps::custom_rwlock::RwLock;
+    ///
+    /// struct Pool {
+    ///     jobs: Vec<u32>,
+    ///     submitted: u64,
+    /// }
+    ///
+    /// let lock = RwLock::new(Pool { jobs: Vec::new(), submitted: 0 });
+    /// let mut guard = lock.write().unwrap();
+    /// guard.with_field(|pool| &mut pool.jobs, |jobs| jobs.push(7));
+    /// let submitted = guard.with_field(|pool| &mut pool.submitted, |count| {
+    ///     *count += 1;
+    ///     *count
+    /// });
+    /// assert_eq!((guard.jobs.len(), submitted), (1, 1));
+    /// guard.release();
+    /// ```
+    pub fn with_field<U, R, Fsel, Fact>(&mut self, sel: Fsel, act: Fact) -> R
+    where
+        U: ?Sized,
+        Fsel: FnOnce(&mut T) -> &mut U,
+        Fact: FnOnce(&mut U) -> R,
+    {
+        act(sel(self))
+    }
+}
+
+impl<T: ?Sized> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.as_ref().expect("guard is held until released").0
+    }
+}
+
+impl<T: ?Sized> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner.as_mut().expect("guard is held until released").0
+    }
+}
+
+impl<T: ?Sized> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.unlock() && !std::thread::panicking() {
+            panic!(
+                "WriteGuard on {} dropped without being released",
+                self.lock.describe()
+            );
+        }
+    }
+}
+
+// SAFETY: as for `ReadGuard`, a `&WriteGuard` only gives access to `&T`.
+unsafe impl<T: ?Sized + Sync> Sync for WriteGuard<'_, T> {}
+
+fn unlock_read<T: ?Sized>(
+    lock: &RwLock<T>,
+    inner: &mut Option<(RwLockReadGuard<'_, T>, Acquired)>,
+) -> bool {
+    match inner.take() {
+        Some((inner, acquired)) => {
+            drop(inner);
+            lock.on_release(acquired);
+            true
+        }
+        None => false,
+    }
+}
+
+fn unlock_write<T: ?Sized>(
+    lock: &RwLock<T>,
+    inner: &mut Option<(RwLockWriteGuard<'_, T>, Acquired)>,
+) -> bool {
+    match inner.take() {
+        Some((inner, acquired)) => {
+            // A write cut short by a panic leaves a value that poisoning hides, which must not
+            // be published nor counted as a write.
+            if !acquired.poisons() {
+                lock.finish_write(&inner);
+            }
+            drop(inner);
+            lock.on_release(acquired);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Shared access to the value protected by a [`RwLock`], released when dropped.
+pub struct AutoReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    inner: Option<(RwLockReadGuard<'a, T>, Acquired)>,
+    _not_send: NotSend,
+}
+
+impl<'a, T: ?Sized> AutoReadGuard<'a, T> {
+    #[track_caller]
+    pub(super) fn new(lock: &'a RwLock<T>, inner: RwLockReadGuard<'a, T>) -> Self {
+        ReadGuard::new(lock, inner).into_auto()
+    }
+
+    /// Turns this guard into a strict [`ReadGuard`], which must then be explicitly released.
+    pub fn into_strict(mut self) -> ReadGuard<'a, T> {
+        ReadGuard {
+            lock: self.lock,
+            inner: self.inner.take(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for AutoReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.as_ref().expect("guard is held until dropped").0
+    }
+}
+
+impl<T: ?Sized> Drop for AutoReadGuard<'_, T> {
+    fn drop(&mut self) {
+        unlock_read(self.lock, &mut self.inner);
+    }
+}
+
+// SAFETY: same reasoning as for `ReadGuard`.
+unsafe impl<T: ?Sized + Sync> Sync for AutoReadGuard<'_, T> {}
+
+/// Exclusive access to the value protected by a [`RwLock`], released when dropped.
+pub struct AutoWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    inner: Option<(RwLockWriteGuard<'a, T>, Acquired)>,
+    _not_send: NotSend,
+}
+
+impl<'a, T: ?Sized> AutoWriteGuard<'a, T> {
+    #[track_caller]
+    pub(super) fn new(lock: &'a RwLock<T>, inner: RwLockWriteGuard<'a, T>) -> Self {
+        WriteGuard::new(lock, inner).into_auto()
+    }
+
+    /// Turns this guard into a strict [`WriteGuard`], which must then be explicitly released.
+    pub fn into_strict(mut self) -> WriteGuard<'a, T> {
+        WriteGuard {
+            lock: self.lock,
+            inner: self.inner.take(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for AutoWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.as_ref().expect("guard is held until dropped").0
+    }
+}
+
+impl<T: ?Sized> DerefMut for AutoWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner.as_mut().expect("guard is held until dropped").0
+    }
+}
+
+impl<T: ?Sized> Drop for AutoWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        unlock_write(self.lock, &mut self.inner);
+    }
+}
+
+// SAFETY: same reasoning as for `ReadGuard`.
+unsafe impl<T: ?Sized + Sync> Sync for AutoWriteGuard<'_, T> {}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A guard that can be held inside a [`GuardBundle`].
+pub trait BundledGuard: private::Sealed {
+    /// Identifies the lock this guard was acquired on.
+    fn describe(&self) -> String;
+
+    /// Releases the guard.
+    fn release(self);
+}
+
+impl<T: ?Sized> private::Sealed for ReadGuard<'_, T> {}
+
+impl<T: ?Sized> BundledGuard for ReadGuard<'_, T> {
+    fn describe(&self) -> String {
+        format!("read {}", self.lock.describe())
+    }
+
+    fn release(self) {
+        ReadGuard::release(self)
+    }
+}
+
+impl<T: ?Sized> private::Sealed for WriteGuard<'_, T> {}
+
+impl<T: ?Sized> BundledGuard for WriteGuard<'_, T> {
+    fn describe(&self) -> String {
+        format!("write {}", self.lock.describe())
+    }
+
+    fn release(self) {
+        WriteGuard::release(self)
+    }
+}
+
+/// A tuple of [`BundledGuard`]s, listed in acquisition order.
+pub trait GuardSet: private::Sealed {
+    /// Describes every guard of the set, in acquisition order.
+    fn describe(&self) -> Vec<String>;
+
+    /// Releases every guard of the set, in reverse acquisition order.
+    fn release_reverse(self);
+}
+
+macro_rules! impl_guard_set {
+    ($($guard:ident . $idx:tt),+ ; $($rev:tt),+) => {
+        impl<$($guard: BundledGuard),+> private::Sealed for ($($guard,)+) {}
+
+        impl<$($guard: BundledGuard),+> GuardSet for ($($guard,)+) {
+            fn describe(&self) -> Vec<String> {
+                vec![$(self.$idx.describe()),+]
+            }
+
+            fn release_reverse(self) {
+                $(self.$rev.release();)+
+            }
+        }
+    };
+}
+
+impl_guard_set!(A.0; 0);
+impl_guard_set!(A.0, B.1; 1, 0);
+impl_guard_set!(A.0, B.1, C.2; 2, 1, 0);
+impl_guard_set!(A.0, B.1, C.2, D.3; 3, 2, 1, 0);
+impl_guard_set!(A.0, B.1, C.2, D.3, E.4; 4, 3, 2, 1, 0);
+impl_guard_set!(A.0, B.1, C.2, D.3, E.4, F.5; 5, 4, 3, 2, 1, 0);
+
+/// Holds several guards as a single release unit.
+///
+/// A struct holding guards as plain fields releases them in field declaration order when
+/// dropped, which may not match the order they were acquired in. A `GuardBundle` instead takes a
+/// tuple of guards in acquisition order and releases them in reverse acquisition order on a
+/// single [`GuardBundle::release`] call.
+///
+/// Dropping a bundle without releasing it releases every guard and then panics once, listing all
+/// the locks that were still held.
+pub struct GuardBundle<G: GuardSet> {
+    guards: Option<G>,
+}
+
+impl<G: GuardSet> GuardBundle<G> {
+    /// Bundles `guards`, which must be listed in the order they were acquired.
+    pub fn new(guards: G) -> Self {
+        GuardBundle {
+            guards: Some(guards),
+        }
+    }
+
+    /// Shared access to the bundled guards.
+    pub fn guards(&self) -> &G {
+        self.guards
+            .as_ref()
+            .expect("guards are held until released")
+    }
+
+    /// Mutable access to the bundled guards.
+    pub fn guards_mut(&mut self) -> &mut G {
+        self.guards
+            .as_mut()
+            .expect("guards are held until released")
+    }
+
+    /// Releases every guard in reverse acquisition order.
+    pub fn release(mut self) {
+        if let Some(guards) = self.guards.take() {
+            guards.release_reverse();
+        }
+    }
+}
+
+impl<G: GuardSet> Drop for GuardBundle<G> {
+    fn drop(&mut self) {
+        if let Some(guards) = self.guards.take() {
+            let held = guards.describe();
+            guards.release_reverse();
+            if !std::thread::panicking() {
+                panic!(
+                    "GuardBundle dropped without being released, held locks: [{}]",
+                    held.join(", ")
+                );
+            }
+        }
+    }
+}
+
+/// Runs a block with a strict guard that is released on every way out of the block.
+///
+/// ```
+/// use stratum_apps::{custom_rwlock::RwLock, guard_scope};
+///
+/// fn first_even(lock: &RwLock<Vec<u32>>) -> Option<u32> {
+///     guard_scope!(read values = lock => {
+///         let even = values.iter().find(|v| *v % 2 == 0)?;
+///         if *even == 0 {
+///             return None;
+///         }
+///         *even
+///     })
+///     .ok()
+/// }
+///
+/// let lock = RwLock::new(vec![1, 4, 5]);
+/// assert_eq!(first_even(&lock), Some(4));
+/// guard_scope!(write values = lock => values.push(6)).unwrap();
+/// ```
+///
+/// `read` acquires the lock for reading and binds a shared reference to the value, `write` for
+/// writing and binds a mutable one. The block runs in the enclosing function, so `return`, `?`,
+/// `break` and `continue` leave it as they would any block, and the guard is released on the
+/// way out, as when the block completes or panics. Since only the value is bound, the guard
+/// cannot escape the block, nor be released twice. Evaluates to the value of the block, or to a
+/// [`PoisonError`](std::sync::PoisonError) without running it if the lock is poisoned.
+#[macro_export]
+macro_rules! guard_scope {
+    (read $name:ident = $lock:expr => $body:expr) => {
+        match $lock.read() {
+            Ok(guard) => {
+                let scoped = $crate::custom_rwlock::ScopedGuard::new(guard);
+                let $name = &**scoped;
+                Ok($body)
+            }
+            Err(_) => Err(::std::sync::PoisonError::new(())),
+        }
+    };
+    (write $name:ident = $lock:expr => $body:expr) => {
+        match $lock.write() {
+            Ok(guard) => {
+                let mut scoped = $crate::custom_rwlock::ScopedGuard::new(guard);
+                let $name = &mut **scoped;
+                Ok($body)
+            }
+            Err(_) => Err(::std::sync::PoisonError::new(())),
+        }
+    };
+}
+
+/// Strict guard released when dropped, on behalf of a [`guard_scope!`](crate::guard_scope).
+#[doc(hidden)]
+pub struct ScopedGuard<G: BundledGuard>(Option<G>);
+
+impl<G: BundledGuard> ScopedGuard<G> {
+    pub fn new(guard: G) -> Self {
+        ScopedGuard(Some(guard))
+    }
+}
+
+impl<G: BundledGuard> Deref for ScopedGuard<G> {
+    type Target = G;
+
+    fn deref(&self) -> &G {
+        self.0.as_ref().expect("guard is held until dropped")
+    }
+}
+
+impl<G: BundledGuard> DerefMut for ScopedGuard<G> {
+    fn deref_mut(&mut self) -> &mut G {
+        self.0.as_mut().expect("guard is held until dropped")
+    }
+}
+
+impl<G: BundledGuard> Drop for ScopedGuard<G> {
+    fn drop(&mut self) {
+        if let Some(guard) = self.0.take() {
+            guard.release();
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Contention benchmark for lock-protected state, available with the `test-util` feature.
+//!
+//! [`ContentionHarness`] hammers a lock from a configurable number of reader and writer threads
+//! and reports throughput and per-thread worst-case latency, which is where starvation shows up.
+//! The operations are plain closures doing their own locking, so the same run can be repeated
+//! against [`RwLock`](super::RwLock), [`FairRwLock`](super::FairRwLock) or any structure built
+//! on top of them.
+
+use std::{
+    sync::Barrier,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Runs reader and writer threads against a lock for a fixed duration.
+#[derive(Debug, Clone)]
+pub struct ContentionHarness {
+    readers: usize,
+    writers: usize,
+    duration: Duration,
+}
+
+/// Statistics of one harness thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadStats {
+    /// Operations completed.
+    pub ops: u64,
+    /// Longest single operation, lock acquisition included.
+    pub max_wait: Duration,
+}
+
+/// Outcome of a [`ContentionHarness::run`].
+#[derive(Debug, Clone)]
+pub struct ContentionReport {
+    /// Statistics of every reader thread.
+    pub readers: Vec<ThreadStats>,
+    /// Statistics of every writer thread.
+    pub writers: Vec<ThreadStats>,
+    /// Wall-clock duration of the run.
+    pub elapsed: Duration,
+}
+
+impl ContentionReport {
+    /// Operations completed by every thread.
+    pub fn total_ops(&self) -> u64 {
+        self.threads().map(|stats| stats.ops).sum()
+    }
+
+    /// Operations completed per second, across every thread.
+    pub fn throughput(&self) -> f64 {
+        self.total_ops() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Longest single operation of any thread.
+    pub fn max_wait(&self) -> Duration {
+        self.threads()
+            .map(|stats| stats.max_wait)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Number of threads that did not complete a single operation.
+    pub fn starved_threads(&self) -> usize {
+        self.threads().filter(|stats| stats.ops == 0).count()
+    }
+
+    fn threads(&self) -> impl Iterator<Item = &ThreadStats> {
+        self.readers.iter().chain(&self.writers)
+    }
+}
+
+impl ContentionHarness {
+    /// Creates a harness running `readers` reader and `writers` writer threads, for 100ms.
+    pub fn new(readers: usize, writers: usize) -> Self {
+        ContentionHarness {
+            readers,
+            writers,
+            duration: Duration::from_millis(100),
+        }
+    }
+
+    /// Sets how long the threads keep running.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Calls `read` and `write` in a loop from the reader and writer threads respectively, until
+    /// the duration elapses.
+    ///
+    /// Every thread starts at the same time. An operation in progress when the duration elapses
+    /// is completed, so a thread starved by the others is reported with its actual wait.
+    pub fn run<R, W>(&self, read: R, write: W) -> ContentionReport
+    where
+        R: Fn() + Sync,
+        W: Fn() + Sync,
+    {
+        let start = Barrier::new(self.readers + self.writers + 1);
+        let (readers, writers, elapsed) = thread::scope(|s| {
+            let (start, duration, read, write) = (&start, self.duration, &read, &write);
+            let readers: Vec<_> = (0..self.readers)
+                .map(|_| s.spawn(move || worker(start, duration, read)))
+                .collect();
+            let writers: Vec<_> = (0..self.writers)
+                .map(|_| s.spawn(move || worker(start, duration, write)))
+                .collect();
+            start.wait();
+            let began = Instant::now();
+            let join = |threads: Vec<thread::ScopedJoinHandle<'_, ThreadStats>>| {
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().expect("harness operations do not panic"))
+                    .collect::<Vec<_>>()
+            };
+            (join(readers), join(writers), began.elapsed())
+        });
+        ContentionReport {
+            readers,
+            writers,
+            elapsed,
+        }
+    }
+}
+
+// Loop of one harness thread, started with the others at `start`.
+fn worker(start: &Barrier, duration: Duration, op: &dyn Fn()) -> ThreadStats {
+    start.wait();
+    let deadline = Instant::now() + duration;
+    let mut stats = ThreadStats {
+        ops: 0,
+        max_wait: Duration::ZERO,
+    };
+    while Instant::now() < deadline {
+        let began = Instant::now();
+        op();
+        stats.ops += 1;
+        stats.max_wait = stats.max_wait.max(began.elapsed());
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_rwlock::{FairRwLock, RwLock};
+
+    #[test]
+    fn test_harness_reports_throughput() {
+        let harness = ContentionHarness::new(3, 1).duration(Duration::from_millis(20));
+        let lock = RwLock::new(0u64);
+        let report = harness.run(
+            || lock.super_safe_read(|v| assert!(*v < u64::MAX)),
+            || lock.super_safe_write(|v| *v += 1),
+        );
+        assert_eq!((report.readers.len(), report.writers.len()), (3, 1));
+        assert!(report.total_ops() > 0 && report.throughput() > 0.0);
+        assert_eq!(report.writers[0].ops, lock.super_safe_read(|v| *v));
+
+        let fair = FairRwLock::new(0u64);
+        let report = harness.run(|| fair.safe_read(|_| ()), || fair.safe_write(|v| *v += 1));
+        assert_eq!(report.starved_threads(), 0);
+    }
+}
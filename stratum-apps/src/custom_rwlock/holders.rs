@@ -0,0 +1,134 @@
+//! Per-thread record of the locks currently held, used for debug assertions.
+//!
+//! Tracking is only compiled in debug builds. In release builds [`Held`] is a zero sized token
+//! and every query reports nothing held.
+
+#[cfg(debug_assertions)]
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// How a lock is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HeldMode {
+    Read,
+    Write,
+}
+
+#[cfg(debug_assertions)]
+static NEXT_THREAD_INDEX: AtomicU64 = AtomicU64::new(1);
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static HELD: RefCell<Vec<(usize, HeldMode)>> = const { RefCell::new(Vec::new()) };
+    static THREAD_INDEX: u64 = NEXT_THREAD_INDEX.fetch_add(1, Ordering::Relaxed);
+    static READ_ONLY: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Small number identifying the current thread in diagnostics, starting at 1.
+#[cfg(debug_assertions)]
+pub(crate) fn current_thread_index() -> u64 {
+    THREAD_INDEX.with(|index| *index)
+}
+
+/// Registers a lock as held by the current thread until dropped.
+pub(crate) struct Held {
+    #[cfg(debug_assertions)]
+    addr: usize,
+    #[cfg(debug_assertions)]
+    mode: HeldMode,
+}
+
+impl Held {
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn new(addr: usize, mode: HeldMode) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            HELD.with(|held| held.borrow_mut().push((addr, mode)));
+            Held { addr, mode }
+        }
+        #[cfg(not(debug_assertions))]
+        Held {}
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        {
+            // `try_with` because guards can be dropped while thread locals are torn down.
+            let _ = HELD.try_with(|held| {
+                let mut held = held.borrow_mut();
+                if let Some(pos) = held.iter().rposition(|e| *e == (self.addr, self.mode)) {
+                    held.remove(pos);
+                }
+            });
+        }
+    }
+}
+
+/// Returns how the current thread holds the lock at `addr`, if it holds it at all.
+///
+/// Always `None` in release builds.
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+pub(crate) fn held_by_current_thread(addr: usize) -> Option<HeldMode> {
+    #[cfg(debug_assertions)]
+    {
+        HELD.with(|held| {
+            held.borrow()
+                .iter()
+                .rev()
+                .find(|(a, _)| *a == addr)
+                .map(|(_, mode)| *mode)
+        })
+    }
+    #[cfg(not(debug_assertions))]
+    None
+}
+
+/// Marks the lock at `addr` as read-only for the current thread until dropped.
+pub(crate) struct ReadOnly {
+    #[cfg(debug_assertions)]
+    addr: usize,
+}
+
+impl ReadOnly {
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn new(addr: usize) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            READ_ONLY.with(|read_only| read_only.borrow_mut().push(addr));
+            ReadOnly { addr }
+        }
+        #[cfg(not(debug_assertions))]
+        ReadOnly {}
+    }
+}
+
+impl Drop for ReadOnly {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        {
+            let _ = READ_ONLY.try_with(|read_only| {
+                let mut read_only = read_only.borrow_mut();
+                if let Some(pos) = read_only.iter().rposition(|addr| *addr == self.addr) {
+                    read_only.remove(pos);
+                }
+            });
+        }
+    }
+}
+
+/// Returns whether the current thread marked the lock at `addr` as read-only.
+///
+/// Always `false` in release builds.
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+pub(crate) fn is_read_only(addr: usize) -> bool {
+    #[cfg(debug_assertions)]
+    {
+        READ_ONLY.with(|read_only| read_only.borrow().contains(&addr))
+    }
+    #[cfg(not(debug_assertions))]
+    false
+}
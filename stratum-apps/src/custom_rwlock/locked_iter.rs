@@ -0,0 +1,95 @@
+//! Iteration over a collection projected out of the locked value, see
+//! [`RwLock::read_project_iter`].
+
+use super::{AutoReadGuard, RwLock};
+use std::{
+    ptr::NonNull,
+    sync::{PoisonError, RwLockReadGuard},
+};
+
+/// A collection inside the value of a [`RwLock`], kept read-locked until this is dropped.
+///
+/// Obtained with [`RwLock::read_project_iter`]. Iterate it by reference, with
+/// [`LockedIter::iter`] or `for item in &locked`: the items borrow from this and not from the
+/// lock, so none of them can outlive the shared lock.
+pub struct LockedIter<'a, T: ?Sized, C: ?Sized> {
+    _guard: AutoReadGuard<'a, T>,
+    items: NonNull<C>,
+}
+
+impl<T: ?Sized, C: ?Sized> LockedIter<'_, T, C> {
+    /// Iterates over the projected collection.
+    pub fn iter<'s>(&'s self) -> <&'s C as IntoIterator>::IntoIter
+    where
+        &'s C: IntoIterator,
+    {
+        self.into_iter()
+    }
+}
+
+impl<'s, T: ?Sized, C: ?Sized> IntoIterator for &'s LockedIter<'_, T, C>
+where
+    &'s C: IntoIterator,
+{
+    type Item = <&'s C as IntoIterator>::Item;
+    type IntoIter = <&'s C as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        // SAFETY: the pointer was derived from the value protected by the lock, which the guard
+        // keeps read-locked for as long as `self` is borrowed.
+        unsafe { self.items.as_ref() }.into_iter()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquires a shared lock and returns the collection `f` selects in the value, to iterate it
+    /// without exposing the rest of the value.
+    ///
+    /// The lock is released when the returned [`LockedIter`] is dropped.
+    #[track_caller]
+    pub fn read_project_iter<C, F>(
+        &self,
+        f: F,
+    ) -> Result<LockedIter<'_, T, C>, PoisonError<RwLockReadGuard<'_, T>>>
+    where
+        C: ?Sized,
+        F: FnOnce(&T) -> &C,
+    {
+        let guard = AutoReadGuard::new(self, self.inner.read().map_err(|e| self.poisoned(e))?);
+        // The pointer targets the value in the lock, not the guard, so moving the guard is fine.
+        let items = NonNull::from(f(&guard));
+        Ok(LockedIter {
+            _guard: guard,
+            items,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Pool {
+        name: &'static str,
+        workers: Vec<u32>,
+    }
+
+    #[test]
+    fn test_iterates_the_projection_until_dropped() {
+        let lock = RwLock::new(Pool {
+            name: "pool",
+            workers: vec![3, 1, 2],
+        });
+        let workers = lock.read_project_iter(|pool| &pool.workers).unwrap();
+        assert_eq!(workers.iter().sum::<u32>(), 6);
+        let mut seen = Vec::new();
+        for worker in &workers {
+            seen.push(*worker);
+        }
+        assert_eq!(seen, [3, 1, 2]);
+        assert!(lock.inner.try_write().is_err());
+        drop(workers);
+        assert!(lock.inner.try_write().is_ok());
+        assert_eq!(lock.super_safe_read(|pool| pool.name), "pool");
+    }
+}
@@ -0,0 +1,199 @@
+//! Guards projecting a [`WriteGuard`] onto disjoint parts of the protected value.
+//!
+//! [`WriteGuard::map_split`] hands out two exclusive parts, [`WriteGuard::split_read_write`] a
+//! shared part and an exclusive one, e.g. to update a field from a stable view of another.
+
+use super::WriteGuard;
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    rc::Rc,
+};
+
+// Type-erased owner of the write guard shared by mapped guards.
+trait Owner {}
+
+// Releases the write guard once the last mapped guard sharing it is dropped.
+struct SharedWrite<'a, T: ?Sized>(Option<WriteGuard<'a, T>>);
+
+impl<T: ?Sized> Owner for SharedWrite<'_, T> {}
+
+impl<T: ?Sized> Drop for SharedWrite<'_, T> {
+    fn drop(&mut self) {
+        if let Some(guard) = self.0.take() {
+            guard.release();
+        }
+    }
+}
+
+/// Exclusive access to part of the value protected by a [`RwLock`](super::RwLock), obtained with
+/// [`WriteGuard::map_split`].
+///
+/// The exclusive lock is released when the last mapped guard obtained from the same
+/// [`WriteGuard`] is dropped.
+pub struct MappedWriteGuard<'a, U: ?Sized> {
+    value: NonNull<U>,
+    _owner: Rc<dyn Owner + 'a>,
+    _marker: PhantomData<&'a mut U>,
+}
+
+/// Shared access to part of the value protected by a [`RwLock`](super::RwLock), obtained with
+/// [`WriteGuard::split_read_write`].
+///
+/// The exclusive lock is still held, and only released when the last mapped guard obtained from
+/// the same [`WriteGuard`] is dropped.
+pub struct MappedReadGuard<'a, U: ?Sized> {
+    value: NonNull<U>,
+    _owner: Rc<dyn Owner + 'a>,
+    _marker: PhantomData<&'a U>,
+}
+
+impl<'a, T: ?Sized> WriteGuard<'a, T> {
+    /// Splits the guard into two guards over disjoint parts of the value, like
+    /// [`slice::split_at_mut`].
+    ///
+    /// `f` returning both references out of the same `&mut T` is what guarantees they do not
+    /// overlap. Both mapped guards share the original guard, which is released exactly once, when
+    /// the second of them is dropped.
+    pub fn map_split<A, B, F>(mut self, f: F) -> (MappedWriteGuard<'a, A>, MappedWriteGuard<'a, B>)
+    where
+        A: ?Sized,
+        B: ?Sized,
+        F: FnOnce(&mut T) -> (&mut A, &mut B),
+    {
+        let (a, b) = f(&mut self);
+        let (a, b) = (NonNull::from(a), NonNull::from(b));
+        // The references point into the lock, not into the guard, so they stay valid once the
+        // guard is moved into the shared owner, which keeps the lock held for as long as either
+        // mapped guard is alive.
+        let owner: Rc<dyn Owner + 'a> = Rc::new(SharedWrite(Some(self)));
+        (
+            MappedWriteGuard {
+                value: a,
+                _owner: owner.clone(),
+                _marker: PhantomData,
+            },
+            MappedWriteGuard {
+                value: b,
+                _owner: owner,
+                _marker: PhantomData,
+            },
+        )
+    }
+
+    /// Splits the guard into a shared guard and an exclusive guard over disjoint parts of the
+    /// value.
+    ///
+    /// As with [`WriteGuard::map_split`], `f` returning both references out of the same `&mut T`
+    /// guarantees they do not overlap, and the original guard is released exactly once, when the
+    /// second mapped guard is dropped.
+    pub fn split_read_write<A, B, F>(
+        mut self,
+        f: F,
+    ) -> (MappedReadGuard<'a, A>, MappedWriteGuard<'a, B>)
+    where
+        A: ?Sized,
+        B: ?Sized,
+        F: FnOnce(&mut T) -> (&A, &mut B),
+    {
+        let (a, b) = f(&mut self);
+        let (a, b) = (NonNull::from(a), NonNull::from(b));
+        // Same as `map_split`: the references point into the lock, held by the shared owner.
+        let owner: Rc<dyn Owner + 'a> = Rc::new(SharedWrite(Some(self)));
+        (
+            MappedReadGuard {
+                value: a,
+                _owner: owner.clone(),
+                _marker: PhantomData,
+            },
+            MappedWriteGuard {
+                value: b,
+                _owner: owner,
+                _marker: PhantomData,
+            },
+        )
+    }
+}
+
+impl<U: ?Sized> Deref for MappedReadGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        // SAFETY: the pointer comes from a `&U` into the locked value, disjoint from the mutable
+        // part, and the exclusive lock is held while `self` is alive.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<U: ?Sized> Deref for MappedWriteGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        // SAFETY: the pointer comes from a `&mut U` into the locked value, disjoint from the
+        // other mapped guard, and the exclusive lock is held while `self` is alive.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<U: ?Sized> DerefMut for MappedWriteGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        // SAFETY: see `deref`, and `&mut self` guarantees this is the only access through `self`.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::RwLock;
+
+    #[derive(Default)]
+    struct Pair {
+        left: Vec<u32>,
+        right: String,
+    }
+
+    #[test]
+    fn test_map_split_mutates_both_halves() {
+        let lock = RwLock::new(Pair::default());
+        let (mut left, mut right) = lock
+            .write()
+            .unwrap()
+            .map_split(|pair| (&mut pair.left, &mut pair.right));
+        left.push(1);
+        right.push('a');
+        drop(left);
+        // The lock is only released once both halves are gone.
+        assert!(lock.inner.try_read().is_err());
+        right.push('b');
+        drop(right);
+
+        assert_eq!(lock.version(), 1);
+        lock.super_safe_read(|pair| {
+            assert_eq!(pair.left, vec![1]);
+            assert_eq!(pair.right, "ab");
+        });
+    }
+
+    #[test]
+    fn test_split_read_write_updates_from_a_stable_view() {
+        let lock = RwLock::new(Pair {
+            left: vec![1, 2, 3],
+            right: String::new(),
+        });
+        let (left, mut right) = lock
+            .write()
+            .unwrap()
+            .split_read_write(|pair| (&pair.left, &mut pair.right));
+        for n in left.iter() {
+            right.push_str(&n.to_string());
+        }
+        drop(right);
+        assert!(lock.inner.try_read().is_err());
+        assert_eq!(left.len(), 3);
+        drop(left);
+
+        assert_eq!(lock.version(), 1);
+        assert_eq!(lock.super_safe_read(|pair| pair.right.clone()), "123");
+    }
+}
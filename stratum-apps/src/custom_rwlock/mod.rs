@@ -0,0 +1,1234 @@
+//! # Custom Read-Write Lock
+//!
+//! Provides a read-write lock built on [`std::sync::RwLock`] that follows the same closure-based
+//! conventions as [`crate::custom_mutex::Mutex`], plus explicit-release guards for the cases where
+//! a closure is not convenient.
+
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, LockResult, PoisonError, RwLock as RwLock_, RwLockReadGuard, RwLockWriteGuard,
+};
+
+mod acquisition;
+mod batch;
+mod builder;
+mod bulk;
+mod cache;
+#[cfg(feature = "async")]
+mod changes;
+mod chunked;
+mod compare;
+mod cow;
+mod cow_read;
+#[cfg(feature = "critical-section")]
+mod critical;
+mod dyn_ext;
+#[cfg(feature = "epoch")]
+mod epoch;
+mod fair;
+#[cfg(feature = "fairness")]
+mod fairness;
+mod fence;
+mod guard;
+#[cfg(feature = "test-util")]
+mod harness;
+mod holders;
+mod locked_iter;
+mod mapped;
+mod owned;
+mod parts;
+mod poison_scope;
+mod priority;
+#[cfg(feature = "profile")]
+mod profile;
+mod queue;
+#[cfg(feature = "lock_api")]
+mod raw;
+#[cfg(feature = "test-util")]
+mod registry;
+mod report;
+mod retry;
+mod sharded;
+mod single_flight;
+mod site_stats;
+mod snapshot;
+mod timeout;
+mod trace;
+mod upgrade;
+mod watchdog;
+mod weak;
+mod yielding;
+
+pub use acquisition::assert_no_outstanding_guards;
+use acquisition::HoldWarn;
+pub use batch::{lock_all_write_by, try_acquire_any, with_all_read, with_all_write};
+#[doc(hidden)]
+pub use batch::{ErasedSlot, MultiLock, ReadSlot, Slot, WriteSlot};
+pub use builder::{BuildError, RwLockBuilder};
+pub use bulk::{BulkReader, BulkWriter};
+pub use cache::OnceCache;
+pub use cow_read::CowRead;
+#[cfg(feature = "critical-section")]
+pub use critical::CriticalSectionRwLock;
+pub use dyn_ext::DynRwLockExt;
+#[cfg(feature = "epoch")]
+pub use epoch::{EpochReadGuard, EpochRwLock};
+pub use fair::{with_all_fair_write, FairRwLock};
+#[doc(hidden)]
+pub use guard::ScopedGuard;
+pub use guard::{
+    AutoReadGuard, AutoWriteGuard, BundledGuard, GuardBundle, GuardSet, ReadGuard, WriteGuard,
+};
+#[cfg(feature = "test-util")]
+pub use harness::{ContentionHarness, ContentionReport, ThreadStats};
+pub use locked_iter::LockedIter;
+pub use mapped::{MappedReadGuard, MappedWriteGuard};
+pub use owned::OwnedReadGuard;
+pub use parts::LockMetadata;
+pub use poison_scope::{run_poison_safe, LockPoisoned};
+#[cfg(feature = "lock_api")]
+pub use raw::RawRwLock;
+#[cfg(feature = "test-util")]
+pub use registry::{held_guards, HeldGuard};
+pub use report::debug_lock_report;
+pub use retry::{Backoff, ExponentialBackoff, Retry, RetryError};
+pub use sharded::ShardedRwLock;
+pub use single_flight::FlightSlot;
+pub use snapshot::ReadAccess;
+pub use timeout::{Budget, LockError, TimeoutStats};
+pub use trace::TraceEntry;
+pub use upgrade::Upgrade;
+pub use watchdog::Deadlock;
+pub use weak::WeakRwLock;
+pub use yielding::YieldingReader;
+
+// Source of the lock ids, see `RwLock::id`.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Custom synchronization primitive for managing shared state with many readers and exclusive
+/// writers.
+///
+/// This custom read-write lock builds on [`std::sync::RwLock`] and mirrors the API of
+/// [`crate::custom_mutex::Mutex`]: closure-based access that releases the lock as soon as the
+/// closure completes, and explicit handling of [`PoisonError`].
+///
+/// ## Advantages
+/// - **Closure-Based Locking:** `safe_read` and `safe_write` encapsulate the locking process,
+///   ensuring the lock is automatically released after the closure completes.
+/// - **Error Handling:** poisoned locks surface as [`PoisonError`] instead of panics.
+/// - **Explicit Release:** `read` and `write` return guards that must be released with
+///   `release()`. Dropping such a guard without releasing it panics, so forgotten releases are
+///   caught at the point where they happen rather than showing up as contention later on.
+pub struct RwLock<T: ?Sized> {
+    label: Option<&'static str>,
+    // Unique across the locks of the process, unlike the address which is reused once a lock is
+    // dropped.
+    id: u64,
+    // Offset of the protected value from the start of the lock, see `data_ptr`.
+    data_offset: usize,
+    // Bumped once per write, see `version`.
+    version: AtomicU64,
+    // Acquisitions not released yet, see `outstanding_guards`.
+    outstanding: AtomicUsize,
+    // Index of the thread holding the write lock, 0 if none, see `debug_lock_report`.
+    #[cfg(debug_assertions)]
+    writer: AtomicU64,
+    hold_warn: Option<HoldWarn>,
+    #[cfg(feature = "profile")]
+    profile: profile::HoldProfile,
+    #[cfg(feature = "fairness")]
+    fairness: Option<fairness::FairnessCounters>,
+    trace: Option<trace::AcquisitionTrace>,
+    sites: Option<site_stats::SiteCounters>,
+    timeouts: Option<timeout::TimeoutCounters>,
+    deadlock: Option<watchdog::DeadlockWatch>,
+    priority: Option<priority::PriorityTracking>,
+    backoff: Option<retry::NewBackoff>,
+    #[cfg(feature = "async")]
+    changes: std::sync::OnceLock<changes::Changes>,
+    snapshot: Option<Box<dyn snapshot::Publish<T>>>,
+    inner: RwLock_<T>,
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new [`RwLock`] instance, storing the initial value inside.
+    pub fn new(v: T) -> Self {
+        RwLock::from_std(RwLock_::new(v))
+    }
+
+    /// Creates a new [`RwLock`] storing `v`, behind an [`Arc`] to be shared between threads.
+    pub fn new_shared(v: T) -> Arc<Self> {
+        Arc::new(RwLock::new(v))
+    }
+
+    /// Wraps a [`std::sync::RwLock`], for interop with code that hands one over.
+    ///
+    /// The poison state is preserved: a poisoned std lock gives a poisoned [`RwLock`].
+    pub fn from_std(inner: RwLock_<T>) -> Self {
+        let mut lock = RwLock {
+            label: None,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            data_offset: 0,
+            version: AtomicU64::new(0),
+            outstanding: AtomicUsize::new(0),
+            #[cfg(debug_assertions)]
+            writer: AtomicU64::new(0),
+            hold_warn: None,
+            #[cfg(feature = "profile")]
+            profile: profile::HoldProfile::default(),
+            #[cfg(feature = "fairness")]
+            fairness: None,
+            trace: None,
+            sites: None,
+            timeouts: None,
+            deadlock: None,
+            priority: None,
+            backoff: None,
+            #[cfg(feature = "async")]
+            changes: std::sync::OnceLock::new(),
+            snapshot: None,
+            inner,
+        };
+        // The layout of `RwLock<T>` is fixed for a given `T`, so the offset of the value stays
+        // valid wherever the lock is moved to. `get_mut` lets us read it without locking.
+        let base = std::ptr::addr_of!(lock) as usize;
+        let data = lock.inner.get_mut().unwrap_or_else(PoisonError::into_inner) as *mut T as usize;
+        lock.data_offset = data - base;
+        lock
+    }
+
+    /// Unwraps the underlying [`std::sync::RwLock`], poison state included.
+    ///
+    /// Diagnostics configured on this lock (hold time warning, deadlock timeout, trace, site
+    /// stats, timeout stats, snapshots) are dropped.
+    pub fn into_std(self) -> RwLock_<T> {
+        self.inner
+    }
+
+    /// Returns a raw pointer to the protected value, without acquiring the lock.
+    ///
+    /// Mirrors [`std::cell::UnsafeCell::get`]. Obtaining the pointer is safe, dereferencing it is
+    /// not: the caller must make sure the access is synchronized, either by holding a guard of
+    /// the matching kind for as long as the pointer is used, or through an external protocol
+    /// (e.g. a C library that only touches the value while the Rust side holds the lock).
+    ///
+    /// The pointer is only valid as long as the lock is neither moved nor dropped.
+    pub fn data_ptr(&self) -> *const T {
+        (self as *const Self as *const u8).wrapping_add(self.data_offset) as *const T
+    }
+
+    /// Mutable counterpart of [`RwLock::data_ptr`].
+    ///
+    /// Writing through the pointer requires the same exclusive access a [`WriteGuard`] gives.
+    pub fn data_ptr_mut(&self) -> *mut T {
+        self.data_ptr() as *mut T
+    }
+
+    /// Consumes the lock and returns the inner value.
+    ///
+    /// A poisoned lock still yields its value, since nobody else can observe it anymore.
+    pub fn into_inner(self) -> T {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Extracts the inner value from a shared lock if `this` is the last reference to it.
+    ///
+    /// Meant for teardown of `Arc<RwLock<T>>` based state. If other references are still alive
+    /// the `Arc` is handed back untouched. Like [`RwLock::into_inner`], a poisoned lock still
+    /// yields its value.
+    pub fn try_into_inner(this: Arc<Self>) -> Result<T, Arc<Self>> {
+        Arc::try_unwrap(this).map(RwLock::into_inner)
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// RwLock safe read.
+    ///
+    /// Safely acquires a shared lock and executes a closure (`thunk`) with a reference to the
+    /// inner value. The lock is released as soon as the closure completes. It explicitly returns
+    /// a [`PoisonError`] containing a [`RwLockReadGuard`] in cases where the lock is poisoned.
+    #[track_caller]
+    pub fn safe_read<F, Ret>(&self, thunk: F) -> Result<Ret, PoisonError<RwLockReadGuard<'_, T>>>
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        // An auto guard keeps the bookkeeping right if `thunk` panics.
+        let lock = AutoReadGuard::new(self, self.inner.read().map_err(|e| self.poisoned(e))?);
+        let return_value = thunk(&lock);
+        drop(lock);
+        Ok(return_value)
+    }
+
+    /// RwLock safe write.
+    ///
+    /// Safely acquires an exclusive lock and executes a closure (`thunk`) with a mutable
+    /// reference to the inner value. The lock is released as soon as the closure completes. It
+    /// explicitly returns a [`PoisonError`] containing a [`RwLockWriteGuard`] in cases where the
+    /// lock is poisoned.
+    ///
+    /// To prevent poison lock errors, unwraps should never be used within the closure. The result
+    /// should always be returned and handled outside of the safe write.
+    #[track_caller]
+    pub fn safe_write<F, Ret>(&self, thunk: F) -> Result<Ret, PoisonError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        self.debug_assert_writable();
+        let mut lock = AutoWriteGuard::new(self, self.inner.write().map_err(|e| self.poisoned(e))?);
+        let return_value = thunk(&mut lock);
+        drop(lock);
+        Ok(return_value)
+    }
+
+    /// Runs `w` under an exclusive lock, then `rd` on the value `w` left, with no other write in
+    /// between.
+    ///
+    /// Downgrading a std write guard is not stable on the toolchain this crate builds with, so
+    /// `rd` runs before the exclusive lock is released rather than on a downgraded guard: the
+    /// state it observes is exactly the one `w` wrote, but readers are kept out until it returns
+    /// too. Keep `rd` short.
+    #[track_caller]
+    pub fn write_then_read_scoped<W, Rd, R>(
+        &self,
+        w: W,
+        rd: Rd,
+    ) -> Result<R, PoisonError<RwLockWriteGuard<'_, T>>>
+    where
+        W: FnOnce(&mut T),
+        Rd: FnOnce(&T) -> R,
+    {
+        self.safe_write(|value| {
+            w(value);
+            rd(value)
+        })
+    }
+
+    /// RwLock super safe read.
+    ///
+    /// Same as `safe_read`, panicking if the lock is poisoned.
+    #[track_caller]
+    pub fn super_safe_read<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        self.safe_read(thunk).unwrap()
+    }
+
+    /// RwLock super safe write.
+    ///
+    /// Same as `safe_write`, panicking if the lock is poisoned.
+    #[track_caller]
+    pub fn super_safe_write<F, Ret>(&self, thunk: F) -> Ret
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        self.safe_write(thunk).unwrap()
+    }
+
+    /// Clones the part of the value selected by `f` into a new [`Arc`].
+    ///
+    /// The shared lock is only held for the projection and the clone. The returned `Arc` is an
+    /// owned snapshot: it can be shared and kept around without holding the lock, and is not
+    /// affected by later writes.
+    #[track_caller]
+    pub fn read_project_arc<U, F>(
+        self: &Arc<Self>,
+        f: F,
+    ) -> Result<Arc<U>, PoisonError<RwLockReadGuard<'_, T>>>
+    where
+        U: Clone,
+        F: FnOnce(&T) -> &U,
+    {
+        self.safe_read(|value| Arc::new(f(value).clone()))
+    }
+
+    /// Acquires a shared lock and returns an explicit-release [`ReadGuard`].
+    ///
+    /// The guard must be given back with [`ReadGuard::release`]. If the lock is poisoned the
+    /// plain [`RwLockReadGuard`] is returned inside the [`PoisonError`], so error paths never
+    /// have to deal with the release contract.
+    #[track_caller]
+    pub fn read(&self) -> Result<ReadGuard<'_, T>, PoisonError<RwLockReadGuard<'_, T>>> {
+        let guard = self.inner.read().map_err(|e| self.poisoned(e))?;
+        Ok(ReadGuard::new(self, guard))
+    }
+
+    /// Acquires a shared lock and returns the guard only if `validate` accepts the value.
+    ///
+    /// Meant for defensive paths reading data that may be corrupted behind the lock's back, for
+    /// instance by foreign code: a value failing `validate` is reported as [`LockError::Invalid`]
+    /// and the lock is released before returning, as with [`ReadGuard::filter`].
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn read_validated<F>(
+        &self,
+        validate: F,
+    ) -> Result<ReadGuard<'_, T>, LockError<RwLockReadGuard<'_, T>>>
+    where
+        F: FnOnce(&T) -> bool,
+    {
+        self.read()?.filter(validate).ok_or(LockError::Invalid)
+    }
+
+    /// Acquires an exclusive lock and returns an explicit-release [`WriteGuard`].
+    ///
+    /// The guard must be given back with [`WriteGuard::release`]. If the lock is poisoned the
+    /// plain [`RwLockWriteGuard`] is returned inside the [`PoisonError`].
+    #[track_caller]
+    pub fn write(&self) -> Result<WriteGuard<'_, T>, PoisonError<RwLockWriteGuard<'_, T>>> {
+        self.debug_assert_writable();
+        let guard = self.inner.write().map_err(|e| self.poisoned(e))?;
+        Ok(WriteGuard::new(self, guard))
+    }
+
+    /// Acquires a shared lock and returns an [`AutoReadGuard`], released when dropped.
+    #[track_caller]
+    pub fn read_auto(&self) -> Result<AutoReadGuard<'_, T>, PoisonError<RwLockReadGuard<'_, T>>> {
+        let guard = self.inner.read().map_err(|e| self.poisoned(e))?;
+        Ok(AutoReadGuard::new(self, guard))
+    }
+
+    /// Acquires an exclusive lock and returns an [`AutoWriteGuard`], released when dropped.
+    #[track_caller]
+    pub fn write_auto(
+        &self,
+    ) -> Result<AutoWriteGuard<'_, T>, PoisonError<RwLockWriteGuard<'_, T>>> {
+        self.debug_assert_writable();
+        let guard = self.inner.write().map_err(|e| self.poisoned(e))?;
+        Ok(AutoWriteGuard::new(self, guard))
+    }
+
+    /// Acquires a shared lock and returns the plain std [`RwLockReadGuard`].
+    ///
+    /// Escape hatch for code that needs a guard released on drop and cannot use
+    /// [`RwLock::read_auto`]. The acquisition bypasses every diagnostic of this lock: it is not
+    /// tracked as held by the current thread, not checked against the hold time warning and not
+    /// recorded in the hold profile.
+    pub fn raw_read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        self.inner.read()
+    }
+
+    /// Acquires an exclusive lock and returns the plain std [`RwLockWriteGuard`].
+    ///
+    /// Same escape hatch as [`RwLock::raw_read`], with the same lack of diagnostics. Writes made
+    /// through the returned guard are also not counted by [`RwLock::version`].
+    pub fn raw_write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        self.inner.write()
+    }
+
+    /// Acquires a shared lock and returns it together with the current version.
+    ///
+    /// The version is incremented exactly once per write (a released [`WriteGuard`], a
+    /// `safe_write` call or a `get_mut` borrow), so callers can cache a result computed from the
+    /// value keyed by its version and skip recomputing it while the version is unchanged.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn read_with_version(
+        &self,
+    ) -> Result<(ReadGuard<'_, T>, u64), PoisonError<RwLockReadGuard<'_, T>>> {
+        let guard = self.read()?;
+        let version = self.version();
+        Ok((guard, version))
+    }
+
+    /// Current version of the protected value, see [`RwLock::read_with_version`].
+    ///
+    /// Reading the version does not take the lock.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    // Must be called with the write lock held, right before releasing it: bumps the version and
+    // publishes the stale snapshot, if enabled, then notifies the subscribers to `changes`. Not
+    // called for writes interrupted by a panic.
+    pub(crate) fn finish_write(&self, value: &T) {
+        self.version.fetch_add(1, Ordering::Release);
+        if let Some(snapshot) = &self.snapshot {
+            snapshot.publish(value);
+        }
+        #[cfg(feature = "async")]
+        self.notify_changes();
+    }
+
+    // Same as `finish_write` for a value that is not to be published: one that deliberately
+    // poisons the lock, see `WriteGuard::poison_if`, or one repaired after a poisoning, see
+    // `unpoison`.
+    pub(crate) fn finish_unpublished_write(&self) {
+        self.version.fetch_add(1, Ordering::Release);
+        #[cfg(feature = "async")]
+        self.notify_changes();
+    }
+
+    /// Returns a mutable reference to the inner value.
+    ///
+    /// No locking is needed since the mutable borrow guarantees exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        *self.version.get_mut() += 1;
+        self.inner.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns whether the lock is poisoned, without acquiring it.
+    ///
+    /// Suitable for health checks and readiness probes.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Clears the poisoned state of the lock.
+    ///
+    /// Only call this once the protected value has been checked or restored to a consistent
+    /// state, as the panic that poisoned the lock may have left it half updated.
+    ///
+    /// Repairs made through [`PoisonError::into_inner`] are not counted as writes, so clearing
+    /// the poison bumps the [`RwLock::version`] on their behalf: results computed from the value
+    /// before the repair, e.g. by [`RwLock::cached_read`], are not served afterwards. The stale
+    /// snapshot, if enabled, is only refreshed by the next write.
+    pub fn unpoison(&self) {
+        if self.inner.is_poisoned() {
+            self.finish_unpublished_write();
+        }
+        self.inner.clear_poison();
+    }
+
+    /// Panics if the current thread holds this lock, for reading or for writing.
+    ///
+    /// Meant to be called before running user callbacks or acquiring another lock from code that
+    /// must not be reentered while this lock is held. Only checked in debug builds, in release
+    /// builds this is a no-op.
+    #[track_caller]
+    pub fn debug_assert_not_held_by_current_thread(&self) {
+        if let Some(mode) = holders::held_by_current_thread(self.addr()) {
+            panic!(
+                "{} is already held for {:?} by the current thread",
+                self.describe(),
+                mode
+            );
+        }
+    }
+
+    /// Runs `f`, panicking if the current thread tries to write to this lock meanwhile.
+    ///
+    /// Enforces phase-based access discipline, e.g. no mutation while serving a batch: any
+    /// exclusive acquisition of this lock by the current thread during `f` panics before
+    /// acquiring the lock, so it is neither poisoned nor deadlocked. Writes from other threads
+    /// and through [`RwLock::raw_write`] are not checked. Only checked in debug builds, in
+    /// release builds this just runs `f`.
+    pub fn assert_read_only_during<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let _read_only = holders::ReadOnly::new(self.addr());
+        f()
+    }
+
+    // Called by every exclusive acquisition, before acquiring.
+    #[track_caller]
+    pub(crate) fn debug_assert_writable(&self) {
+        if holders::is_read_only(self.addr()) {
+            panic!(
+                "{} written during a read-only phase of the current thread",
+                self.describe()
+            );
+        }
+    }
+
+    /// Address of the lock, used as its identity.
+    ///
+    /// Stable for as long as the lock is neither moved nor dropped, which always holds for a lock
+    /// behind an [`Arc`], so it can key registries of locks. Once the lock is dropped the address
+    /// may be reused by another one.
+    pub fn addr(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    /// Returns whether two handles point to the same lock, without locking either.
+    ///
+    /// Like [`Arc::ptr_eq`], useful to deduplicate subscribers stored as `Arc<RwLock<T>>`.
+    pub fn same_lock(a: &Arc<Self>, b: &Arc<Self>) -> bool {
+        Arc::ptr_eq(a, b)
+    }
+
+    /// Label set with [`RwLockBuilder::label`], if any.
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
+    }
+
+    /// Short human readable identifier of this lock, used in panic and log messages.
+    pub(crate) fn describe(&self) -> String {
+        let lock = format!(
+            "RwLock<{}>@{:p}",
+            std::any::type_name::<T>(),
+            self as *const Self as *const ()
+        );
+        match self.label {
+            Some(label) => format!("{label} ({lock})"),
+            None => lock,
+        }
+    }
+}
+
+impl<T: Clone> RwLock<T> {
+    /// Returns a copy of the value if the lock is immediately available for reading.
+    ///
+    /// Never blocks: `None` is returned if a writer holds the lock, or if it is poisoned. Meant
+    /// for metrics and fast-path branches that would rather skip a value than wait for it.
+    #[track_caller]
+    pub fn peek_copy(&self) -> Option<T>
+    where
+        T: Copy,
+    {
+        self.peek_clone()
+    }
+
+    /// Same as [`RwLock::peek_copy`], cloning the value.
+    #[track_caller]
+    pub fn peek_clone(&self) -> Option<T> {
+        let guard = AutoReadGuard::new(self, self.inner.try_read().ok()?);
+        Some(T::clone(&guard))
+    }
+
+    /// Runs `f` as an all-or-nothing update, under a single write lock.
+    ///
+    /// `f` works on a clone of the value, which only replaces it if `f` returns `Ok`. If `f`
+    /// returns `Err` or panics, the value is left exactly as it was, whatever `f` changed before
+    /// failing. A panic still poisons the lock, like any panic while holding it. The outer
+    /// `Result` reports a lock that was already poisoned, the inner one the outcome of `f`.
+    #[track_caller]
+    pub fn transaction<F, R, E>(
+        &self,
+        f: F,
+    ) -> Result<Result<R, E>, PoisonError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnOnce(&mut T) -> Result<R, E>,
+    {
+        self.safe_write(|value| {
+            let mut draft = value.clone();
+            let result = f(&mut draft)?;
+            *value = draft;
+            Ok(result)
+        })
+    }
+}
+
+impl<T: Clone + PartialEq> RwLock<T> {
+    /// Returns a clone of the value if it differs from `last`, under a single shared lock.
+    ///
+    /// Meant for polling: the caller keeps the value it last acted upon and only gets a new one
+    /// when there is something to act upon.
+    #[track_caller]
+    pub fn read_if_changed(
+        &self,
+        last: &T,
+    ) -> Result<Option<T>, PoisonError<RwLockReadGuard<'_, T>>> {
+        self.safe_read(|value| (value != last).then(|| value.clone()))
+    }
+
+    /// Same as [`RwLock::read_if_changed`], skipping the lock and the comparison altogether if no
+    /// write happened since the version `since`.
+    ///
+    /// Also returns the current version, to pass as `since` to the next call. Writes made through
+    /// [`RwLock::raw_write`] do not change the version and thus go unnoticed.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn read_if_changed_since(
+        &self,
+        since: u64,
+        last: &T,
+    ) -> Result<(Option<T>, u64), PoisonError<RwLockReadGuard<'_, T>>> {
+        if self.version() == since {
+            return Ok((None, since));
+        }
+        self.safe_read(|value| ((value != last).then(|| value.clone()), self.version()))
+    }
+
+    /// Replaces the value with `new` if it equals `expected`, under a single write lock.
+    ///
+    /// Compare-and-swap over the whole value, for coarse-grained optimistic updates: on a
+    /// mismatch, `new` is dropped and the inner `Err` holds a clone of the current value, to
+    /// compute the next attempt from.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn compare_exchange(
+        &self,
+        expected: &T,
+        new: T,
+    ) -> Result<Result<(), T>, PoisonError<RwLockWriteGuard<'_, T>>> {
+        self.safe_write(|value| {
+            if value == expected {
+                *value = new;
+                Ok(())
+            } else {
+                Err(value.clone())
+            }
+        })
+    }
+}
+
+impl<T: Clone> RwLock<Vec<T>> {
+    /// Clones the elements into an [`Arc<[T]>`](Arc), to share a consistent view of them with
+    /// many consumers.
+    ///
+    /// Every element is cloned once, in a single allocation, under a brief shared lock. Consumers
+    /// then clone the `Arc`, which only bumps a reference count, and read it without any lock. The
+    /// snapshot is not affected by later writes.
+    #[track_caller]
+    pub fn snapshot_arc(&self) -> Result<Arc<[T]>, PoisonError<RwLockReadGuard<'_, Vec<T>>>> {
+        self.safe_read(|items| Arc::from(items.as_slice()))
+    }
+}
+
+impl<T: Default> RwLock<T> {
+    /// Replaces the value with the one computed by `f` from the current one, under a single
+    /// write lock.
+    ///
+    /// Unlike [`RwLock::safe_write`], `f` receives the value by ownership, which suits by-value
+    /// transformations such as consuming a state into the next one. The value is moved out with
+    /// [`mem::take`](std::mem::take), so if `f` panics the lock is poisoned and left holding
+    /// `T::default()`.
+    #[track_caller]
+    pub fn replace_with<F>(&self, f: F) -> Result<(), PoisonError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnOnce(T) -> T,
+    {
+        self.safe_write(|value| *value = f(std::mem::take(value)))
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T, C: FromIterator<T>> FromIterator<T> for RwLock<C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        RwLock::new(C::from_iter(iter))
+    }
+}
+
+/// Extends the collection in place. The mutable borrow guarantees exclusive access, so no lock
+/// is taken, and a poisoned lock is extended like a healthy one.
+impl<T, C: Extend<T>> Extend<T> for RwLock<C> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.get_mut().extend(iter);
+    }
+}
+
+/// Extends the collection of a shared lock under a single write lock, held for the whole
+/// iterator. Panics if the lock is poisoned, like [`RwLock::super_safe_write`].
+impl<T, C: Extend<T>> Extend<T> for &RwLock<C> {
+    #[track_caller]
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.super_safe_write(|collection| collection.extend(iter));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guard_scope;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    fn test_safe_read_and_write() {
+        let lock = RwLock::new(1u32);
+        lock.safe_write(|i| *i += 1).unwrap();
+        assert_eq!(lock.safe_read(|i| *i).unwrap(), 2);
+        lock.super_safe_write(|i| *i = (*i).checked_add(1).unwrap_or_default());
+        assert_eq!(lock.super_safe_read(|i| *i), 3);
+    }
+
+    #[test]
+    fn test_explicit_release() {
+        let lock = RwLock::new(vec![1u32]);
+        let mut guard = lock.write().unwrap();
+        guard.push(2);
+        guard.release();
+        let guard = lock.read().unwrap();
+        assert_eq!(*guard, vec![1, 2]);
+        guard.release();
+        assert_eq!(lock.into_inner(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_dropping_unreleased_guard_panics() {
+        let lock = RwLock::new(0u8);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = lock.write().unwrap();
+        }));
+        assert!(result.is_err());
+        // The underlying lock is freed before the panic, so it is neither held nor poisoned.
+        lock.safe_write(|v| *v = 1).unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_write_during_read_only_phase_panics() {
+        let lock = RwLock::new(0u8);
+        let sum = lock.assert_read_only_during(|| {
+            let sum = lock.super_safe_read(|v| *v) + lock.super_safe_read(|v| *v);
+            let result = catch_unwind(AssertUnwindSafe(|| lock.safe_write(|v| *v = 1)));
+            assert!(result.is_err());
+            sum
+        });
+        assert_eq!(sum, 0);
+        // The failed write neither acquired nor poisoned the lock, and the phase is over.
+        assert!(!lock.is_poisoned());
+        lock.safe_write(|v| *v = 1).unwrap();
+    }
+
+    #[test]
+    fn test_lock_identity() {
+        let a = Arc::new(RwLock::new(0u8));
+        let b = Arc::new(RwLock::new(0u8));
+        assert!(RwLock::same_lock(&a, &a.clone()));
+        assert!(!RwLock::same_lock(&a, &b));
+        assert_eq!(a.addr(), a.clone().addr());
+        assert_ne!(a.addr(), b.addr());
+    }
+
+    #[test]
+    fn test_peek_never_blocks() {
+        let lock = RwLock::new(7u32);
+        assert_eq!(lock.peek_copy(), Some(7));
+        let writer = lock.write().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(lock.peek_copy(), None));
+        });
+        writer.release();
+        assert_eq!(RwLock::new(vec![1]).peek_clone(), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_transaction_commits_or_rolls_back() {
+        let lock = RwLock::new(vec![1u32]);
+        let failed: Result<(), &str> = lock
+            .transaction(|values| {
+                values.push(2);
+                Err("second step failed")
+            })
+            .unwrap();
+        assert_eq!(failed, Err("second step failed"));
+        assert_eq!(lock.super_safe_read(|v| v.clone()), vec![1]);
+
+        let committed = lock.transaction(|values| {
+            values.push(2);
+            Ok::<_, ()>(values.len())
+        });
+        assert_eq!(committed.unwrap(), Ok(2));
+        assert_eq!(lock.super_safe_read(|v| v.clone()), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_replace_with_consumes_the_previous_value() {
+        let lock = RwLock::new(vec![1u32, 2]);
+        lock.replace_with(|old| {
+            assert_eq!(old, vec![1, 2]);
+            old.into_iter().map(|v| v * 10).collect()
+        })
+        .unwrap();
+        assert_eq!(lock.super_safe_read(|v| v.clone()), vec![10, 20]);
+        assert_eq!(lock.version(), 1);
+    }
+
+    #[test]
+    fn test_raw_guards_drop_normally() {
+        let lock = RwLock::new(1);
+        let guard = lock.raw_read().unwrap();
+        assert_eq!(*guard, 1);
+        drop(guard);
+        *lock.raw_write().unwrap() = 2;
+        assert_eq!(lock.super_safe_read(|v| *v), 2);
+        assert_eq!(lock.version(), 0);
+    }
+
+    #[test]
+    fn test_extend_takes_the_write_lock_once() {
+        let mut lock: RwLock<Vec<u32>> = (0..3).collect();
+        lock.extend(3..5);
+        assert_eq!(lock.version(), 1);
+
+        let mut shared = &lock;
+        shared.extend(5..100);
+        assert_eq!(shared.version(), 2);
+        assert_eq!(
+            shared.super_safe_read(|v| v.clone()),
+            (0..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_std_round_trip_preserves_poison() {
+        let lock = RwLock::from_std(RwLock_::new(vec![1]));
+        lock.super_safe_write(|v| v.push(2));
+        let std_lock = lock.into_std();
+        assert_eq!(*std_lock.read().unwrap(), vec![1, 2]);
+
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = std_lock.write().unwrap();
+            panic!("poison the std lock");
+        }));
+        let lock = RwLock::from_std(std_lock);
+        assert!(lock.is_poisoned());
+        assert!(lock.into_std().is_poisoned());
+    }
+
+    #[test]
+    fn test_projected_arc_outlives_later_writes() {
+        let lock = Arc::new(RwLock::new((String::from("pool"), 1u32)));
+        let name = lock.read_project_arc(|(name, _)| name).unwrap();
+        lock.super_safe_write(|(name, _)| name.push_str("-v2"));
+        assert_eq!(*name, "pool");
+        assert_eq!(*lock.read_project_arc(|(name, _)| name).unwrap(), "pool-v2");
+    }
+
+    #[test]
+    fn test_panicking_closure_is_released() {
+        let lock = RwLock::new(0u8);
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            lock.super_safe_read(|_| panic!("reader failed"));
+        }));
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            lock.super_safe_write(|_| panic!("writer failed"));
+        }));
+        assert_eq!(lock.outstanding_guards(), 0);
+        assert!(lock.is_poisoned());
+    }
+
+    #[test]
+    fn test_filter_keeps_or_releases() {
+        let lock = RwLock::new(5u32);
+        let guard = lock.read().unwrap().filter(|v| *v == 5).unwrap();
+        assert_eq!(*guard, 5);
+        guard.release();
+
+        assert!(lock.read().unwrap().filter(|v| *v == 6).is_none());
+        // The guard was released on the `None` branch, so writers get through.
+        assert!(lock.inner.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_read_validated() {
+        let lock = RwLock::new([1u8, 2, 3]);
+        let checksum_ok = |v: &[u8; 3]| v[0] + v[1] == v[2];
+        let guard = lock.read_validated(checksum_ok).unwrap();
+        assert_eq!(guard[2], 3);
+        guard.release();
+
+        lock.super_safe_write(|v| v[2] = 4);
+        assert!(matches!(
+            lock.read_validated(checksum_ok),
+            Err(LockError::Invalid)
+        ));
+        assert!(lock.inner.try_write().is_ok());
+        assert_eq!(lock.outstanding_guards(), 0);
+    }
+
+    #[test]
+    fn test_write_then_read_sees_its_own_write() {
+        let lock = RwLock::new(0u64);
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        lock.super_safe_write(|v| *v += 1);
+                    }
+                });
+            }
+            for marker in 1_000_000..1_000_200 {
+                let observed = lock
+                    .write_then_read_scoped(
+                        |v| *v = marker,
+                        |v| {
+                            std::thread::yield_now();
+                            *v
+                        },
+                    )
+                    .unwrap();
+                assert_eq!(observed, marker);
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+    }
+
+    #[test]
+    fn test_read_if_changed() {
+        let lock = RwLock::new(vec![1u8]);
+        let last = lock.super_safe_read(Vec::clone);
+        assert_eq!(lock.read_if_changed(&last).unwrap(), None);
+        lock.super_safe_write(|v| v.push(2));
+        assert_eq!(lock.read_if_changed(&last).unwrap(), Some(vec![1, 2]));
+
+        // Unchanged versions short-circuit, even against a stale `last`.
+        let (changed, version) = lock.read_if_changed_since(0, &last).unwrap();
+        assert_eq!((changed, version), (Some(vec![1, 2]), 1));
+        assert_eq!(lock.read_if_changed_since(1, &last).unwrap(), (None, 1));
+        // A write leaving the value equal bumps the version without reporting a change.
+        lock.super_safe_write(|_| ());
+        assert_eq!(
+            lock.read_if_changed_since(1, &vec![1, 2]).unwrap(),
+            (None, 2)
+        );
+    }
+
+    #[test]
+    fn test_data_ptr_matches_guard() {
+        let lock = RwLock::new([7u64; 4]);
+        let guard = lock.read().unwrap();
+        assert_eq!(lock.data_ptr(), &*guard as *const [u64; 4]);
+        assert_eq!(unsafe { (*lock.data_ptr())[0] }, 7);
+        guard.release();
+
+        // The pointer follows the lock when it is moved.
+        let boxed = Box::new(lock);
+        let mut guard = boxed.write().unwrap();
+        assert_eq!(boxed.data_ptr_mut(), &mut *guard as *mut [u64; 4]);
+        unsafe { (*boxed.data_ptr_mut())[1] = 8 };
+        assert_eq!(guard[1], 8);
+        guard.release();
+    }
+
+    #[test]
+    fn test_try_into_inner() {
+        let lock = Arc::new(RwLock::new(String::from("state")));
+        let other = lock.clone();
+        let lock = RwLock::try_into_inner(lock).unwrap_err();
+        drop(other);
+        assert_eq!(RwLock::try_into_inner(lock).unwrap(), "state");
+
+        let poisoned = Arc::new(RwLock::new(1u8));
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            poisoned.super_safe_write(|_| panic!("poison the lock"));
+        }));
+        assert!(poisoned.safe_read(|_| ()).is_err());
+        assert_eq!(RwLock::try_into_inner(poisoned).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_not_held_assertion_is_noop_when_free() {
+        let lock = RwLock::new(0u8);
+        lock.debug_assert_not_held_by_current_thread();
+        lock.read().unwrap().release();
+        lock.safe_write(|_| ()).unwrap();
+        lock.debug_assert_not_held_by_current_thread();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_not_held_assertion_panics_when_held() {
+        let lock = RwLock::new(0u8);
+        let read = catch_unwind(AssertUnwindSafe(|| {
+            let guard = lock.read().unwrap();
+            lock.debug_assert_not_held_by_current_thread();
+            guard.release();
+        }));
+        assert!(read.is_err());
+        let other = RwLock::new(0u8);
+        let write = catch_unwind(AssertUnwindSafe(|| {
+            other.super_safe_write(|_| other.debug_assert_not_held_by_current_thread());
+        }));
+        assert!(write.is_err());
+        // Another thread holding the lock does not trip the assertion.
+        let guard = lock.read().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| lock.debug_assert_not_held_by_current_thread());
+        });
+        guard.release();
+    }
+
+    #[test]
+    fn test_version_bumps_once_per_write() {
+        let lock = RwLock::new(0u32);
+        let (guard, first) = lock.read_with_version().unwrap();
+        guard.release();
+        let (guard, second) = lock.read_with_version().unwrap();
+        guard.release();
+        assert_eq!(first, second);
+
+        let mut guard = lock.write().unwrap();
+        *guard += 1;
+        *guard += 1;
+        guard.release();
+        let (guard, third) = lock.read_with_version().unwrap();
+        assert_eq!(*guard, 2);
+        guard.release();
+        assert_eq!(third, second + 1);
+
+        lock.safe_write(|v| *v += 1).unwrap();
+        assert_eq!(lock.version(), third + 1);
+    }
+
+    #[test]
+    fn test_poison_query_and_reset() {
+        let lock = RwLock::new(0u8);
+        assert!(!lock.is_poisoned());
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            lock.super_safe_write(|_| panic!("poison the lock"));
+        }));
+        assert!(lock.is_poisoned());
+        assert!(lock.safe_read(|_| ()).is_err());
+        lock.unpoison();
+        assert!(!lock.is_poisoned());
+        assert!(lock.safe_read(|_| ()).is_ok());
+    }
+
+    #[test]
+    fn test_strict_and_auto_guard_conversions() {
+        let lock = RwLock::new(vec![1u32]);
+
+        let mut auto = lock.write().unwrap().into_auto();
+        auto.push(2);
+        drop(auto);
+        let version = lock.version();
+        assert_eq!(version, 1);
+
+        let mut strict = lock.write_auto().unwrap().into_strict();
+        strict.push(3);
+        strict.release();
+        assert_eq!(lock.version(), version + 1);
+
+        let auto = lock.read().unwrap().into_auto();
+        assert_eq!(*auto, vec![1, 2, 3]);
+        let strict = auto.into_strict();
+        assert_eq!(strict.len(), 3);
+        strict.release();
+
+        // Dropping an auto guard releases the lock without panicking.
+        drop(lock.read_auto().unwrap());
+        assert!(lock.inner.try_write().is_ok());
+    }
+
+    #[test]
+    fn test_bundle_releases_cleanly() {
+        let a = RwLock::new(1u8);
+        let b = RwLock::new(String::from("b"));
+        let c = RwLock::new(3u64);
+        let mut bundle =
+            GuardBundle::new((a.write().unwrap(), b.read().unwrap(), c.write().unwrap()));
+        *bundle.guards_mut().0 += 1;
+        *bundle.guards_mut().2 += 1;
+        assert_eq!(bundle.guards().1.as_str(), "b");
+        bundle.release();
+        assert_eq!(a.safe_read(|v| *v).unwrap(), 2);
+        assert_eq!(c.safe_read(|v| *v).unwrap(), 4);
+        b.safe_write(|v| v.push('!')).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_bundle_panics_once_listing_locks() {
+        let a = RwLock::new(1u8);
+        let b = RwLock::new(2u16);
+        let c = RwLock::new(3u32);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _bundle =
+                GuardBundle::new((a.write().unwrap(), b.write().unwrap(), c.read().unwrap()));
+        }));
+        let payload = result.unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        for lock in [a.describe(), b.describe(), c.describe()] {
+            assert_eq!(message.matches(&lock).count(), 1, "{message}");
+        }
+        // Every guard was released before panicking.
+        a.safe_write(|v| *v = 0).unwrap();
+        b.safe_write(|v| *v = 0).unwrap();
+        c.safe_write(|v| *v = 0).unwrap();
+    }
+
+    fn sum_until_empty(lock: &RwLock<Vec<&str>>) -> Result<u32, String> {
+        guard_scope!(read values = lock => {
+            let mut sum = 0;
+            for value in values {
+                if value.is_empty() {
+                    return Ok(sum);
+                }
+                sum += value.parse::<u32>().map_err(|e| e.to_string())?;
+            }
+            sum
+        })
+        .map_err(|_| String::from("poisoned"))
+    }
+
+    #[test]
+    fn test_guard_scope_releases_on_every_exit() {
+        let lock = RwLock::new(vec!["1", "2", "", "x"]);
+        // Early return, then `?`, then the end of the block: none leaves the guard behind.
+        assert_eq!(sum_until_empty(&lock), Ok(3));
+        assert_eq!(lock.outstanding_guards(), 0);
+        guard_scope!(write values = lock => values.retain(|v| !v.is_empty())).unwrap();
+        assert!(sum_until_empty(&lock)
+            .unwrap_err()
+            .contains("invalid digit"));
+        assert_eq!(lock.outstanding_guards(), 0);
+        guard_scope!(write values = lock => values.pop()).unwrap();
+        assert_eq!(sum_until_empty(&lock), Ok(3));
+        assert!(lock.inner.try_write().is_ok());
+
+        let poisoned = catch_unwind(AssertUnwindSafe(|| {
+            guard_scope!(write values = lock => {
+                values.clear();
+                assert!(!values.is_empty(), "poison");
+            })
+        }));
+        assert!(poisoned.is_err());
+        assert_eq!(lock.outstanding_guards(), 0);
+        assert_eq!(sum_until_empty(&lock), Err(String::from("poisoned")));
+    }
+
+    #[test]
+    fn test_snapshot_arc_is_detached_from_the_vec() {
+        let lock = RwLock::new(vec![String::from("a"), String::from("b")]);
+        let snapshot = lock.snapshot_arc().unwrap();
+        lock.super_safe_write(|items| {
+            items[0].push('!');
+            items.push(String::from("c"));
+        });
+        assert_eq!(*snapshot, ["a", "b"]);
+        let shared = Arc::clone(&snapshot);
+        assert!(std::ptr::eq(shared.as_ptr(), snapshot.as_ptr()));
+        assert_eq!(Arc::strong_count(&snapshot), 2);
+        assert_eq!(lock.snapshot_arc().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_release_and_then_runs_unlocked() {
+        let lock = RwLock::new(0u8);
+        let mut guard = lock.write().unwrap();
+        *guard = 1;
+        let free = guard.release_and_then(|| {
+            assert_eq!(lock.outstanding_guards(), 0);
+            lock.inner.try_write().is_ok()
+        });
+        assert!(free);
+        assert_eq!(lock.super_safe_read(|v| *v), 1);
+    }
+
+    #[test]
+    fn test_poison_if_gates_bad_state() {
+        let lock = RwLock::new(vec![1u32, 2]);
+        let mut guard = lock.write().unwrap();
+        guard.push(3);
+        assert!(!guard.poison_if(|values| values.is_empty()));
+        assert_eq!(lock.safe_read(|values| values.len()).unwrap(), 3);
+
+        let mut guard = lock.write().unwrap();
+        guard.clear();
+        assert!(guard.poison_if(|values| values.is_empty()));
+        assert!(lock.is_poisoned());
+        assert!(lock.safe_read(|_| ()).is_err());
+        assert_eq!(lock.outstanding_guards(), 0);
+        assert_eq!(lock.version(), 2);
+    }
+
+    #[test]
+    fn test_compare_exchange_swaps_only_on_match() {
+        let lock = RwLock::new(String::from("v1"));
+        let swapped = lock.compare_exchange(&String::from("v1"), String::from("v2"));
+        assert_eq!(swapped.unwrap(), Ok(()));
+        let stale = lock.compare_exchange(&String::from("v1"), String::from("v3"));
+        assert_eq!(stale.unwrap(), Err(String::from("v2")));
+        assert_eq!(lock.super_safe_read(String::clone), "v2");
+    }
+}
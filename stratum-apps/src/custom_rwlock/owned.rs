@@ -0,0 +1,114 @@
+//! Guards owning a reference to their lock, for read locks that outlive the caller's borrow.
+
+use super::{AutoReadGuard, RwLock};
+use std::{
+    ops::Deref,
+    sync::{mpsc, Arc, PoisonError},
+    thread::{self, JoinHandle},
+};
+
+/// Shared access to the value of an `Arc<RwLock<T>>`, keeping the lock alive, released when
+/// dropped.
+///
+/// Obtained with [`RwLock::read_owned`]. Like the other guards it is not `Send`: the std lock must
+/// be released by the thread that acquired it.
+pub struct OwnedReadGuard<T: ?Sized + 'static> {
+    // Borrows from `lock`, declared first so it is dropped before it.
+    guard: AutoReadGuard<'static, T>,
+    lock: Arc<RwLock<T>>,
+}
+
+impl<T: ?Sized> OwnedReadGuard<T> {
+    /// The lock this guard holds.
+    pub fn lock(&self) -> &Arc<RwLock<T>> {
+        &self.lock
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized + 'static> RwLock<T> {
+    /// Acquires a shared lock and returns a guard holding a clone of the `Arc`.
+    ///
+    /// The guard can be stored or returned without borrowing from the caller.
+    #[track_caller]
+    pub fn read_owned(self: &Arc<Self>) -> Result<OwnedReadGuard<T>, PoisonError<()>> {
+        let guard = self.read_auto().map_err(|_| PoisonError::new(()))?;
+        // SAFETY: the guard borrows the lock, which the `Arc` stored next to it keeps alive and
+        // in place for as long as the guard exists, since the guard is dropped first. The
+        // `'static` lifetime never escapes: the value is only lent out through `Deref`.
+        let guard = unsafe {
+            std::mem::transmute::<AutoReadGuard<'_, T>, AutoReadGuard<'static, T>>(guard)
+        };
+        Ok(OwnedReadGuard {
+            guard,
+            lock: self.clone(),
+        })
+    }
+
+    /// Runs `f` on a new thread with an [`OwnedReadGuard`], for long reads such as streaming a
+    /// large buffer to a slow client.
+    ///
+    /// Std guards cannot be sent to another thread, so the spawned thread acquires the lock
+    /// itself, and this only returns once it did: the read observes the value as of when this
+    /// returns, which a writer may have changed since the call, and later writers wait until `f`
+    /// returns, when the guard is released. Returns an error without running `f` if the lock is
+    /// poisoned.
+    pub fn read_then_spawn<F>(self: &Arc<Self>, f: F) -> Result<JoinHandle<()>, PoisonError<()>>
+    where
+        T: Send + Sync,
+        F: FnOnce(OwnedReadGuard<T>) + Send + 'static,
+    {
+        let (acquired, wait_acquired) = mpsc::channel();
+        let lock = self.clone();
+        let handle = thread::spawn(move || match lock.read_owned() {
+            Ok(guard) => {
+                let _ = acquired.send(true);
+                f(guard);
+            }
+            Err(_) => {
+                let _ = acquired.send(false);
+            }
+        });
+        if wait_acquired.recv() == Ok(true) {
+            Ok(handle)
+        } else {
+            let _ = handle.join();
+            Err(PoisonError::new(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_spawned_read_holds_the_lock_until_done() {
+        let lock = Arc::new(RwLock::new(vec![1u32, 2, 3]));
+        let reading = Arc::new(Barrier::new(2));
+        let handle = lock
+            .read_then_spawn({
+                let reading = reading.clone();
+                move |values| {
+                    reading.wait();
+                    assert_eq!(values.iter().sum::<u32>(), 6);
+                    reading.wait();
+                }
+            })
+            .unwrap();
+        reading.wait();
+        assert!(lock.inner.try_write().is_err());
+        reading.wait();
+        handle.join().unwrap();
+        assert!(lock.inner.try_write().is_ok());
+        assert_eq!(lock.outstanding_guards(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Separation of a [`RwLock`] into its value and its configuration and statistics, to rotate the
+//! protected value without losing diagnostic history.
+
+use super::{
+    acquisition::HoldWarn, priority::PriorityTracking, retry::NewBackoff, site_stats::SiteCounters,
+    timeout::TimeoutCounters, trace::AcquisitionTrace, watchdog::DeadlockWatch, RwLock,
+};
+use std::sync::{atomic::Ordering, PoisonError};
+
+/// Configuration and accumulated statistics of a [`RwLock`], obtained with
+/// [`RwLock::into_parts`].
+///
+/// Holds the label, the write count, the options set with the builder and everything they
+/// recorded so far: traces, site and timeout statistics, and the hold profile. Stale snapshots
+/// depend on the type of the value and are not part of it.
+#[derive(Debug)]
+pub struct LockMetadata {
+    label: Option<&'static str>,
+    version: u64,
+    hold_warn: Option<HoldWarn>,
+    #[cfg(feature = "profile")]
+    profile: super::profile::HoldProfile,
+    #[cfg(feature = "fairness")]
+    fairness: Option<super::fairness::FairnessCounters>,
+    trace: Option<AcquisitionTrace>,
+    sites: Option<SiteCounters>,
+    timeouts: Option<TimeoutCounters>,
+    deadlock: Option<DeadlockWatch>,
+    priority: Option<PriorityTracking>,
+    backoff: Option<NewBackoff>,
+}
+
+impl LockMetadata {
+    /// Label of the lock, if it has one.
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
+    }
+
+    /// Number of writes to the lock, see [`RwLock::version`].
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Consumes the lock and returns the inner value with the configuration and statistics of
+    /// the lock.
+    ///
+    /// [`RwLock::from_parts`] then creates a lock carrying on with them, e.g. over a replacement
+    /// value, or the statistics can be logged at shutdown. Like [`RwLock::into_inner`], a
+    /// poisoned lock still yields its value, and the poison flag is not carried over.
+    pub fn into_parts(self) -> (T, LockMetadata) {
+        let metadata = LockMetadata {
+            label: self.label,
+            version: self.version.load(Ordering::Relaxed),
+            hold_warn: self.hold_warn,
+            #[cfg(feature = "profile")]
+            profile: self.profile,
+            #[cfg(feature = "fairness")]
+            fairness: self.fairness,
+            trace: self.trace,
+            sites: self.sites,
+            timeouts: self.timeouts,
+            deadlock: self.deadlock,
+            priority: self.priority,
+            backoff: self.backoff,
+        };
+        let value = self
+            .inner
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        (value, metadata)
+    }
+
+    /// Creates a lock storing `value`, with the configuration and statistics of `metadata`.
+    pub fn from_parts(value: T, metadata: LockMetadata) -> Self {
+        let mut lock = RwLock::new(value);
+        lock.label = metadata.label;
+        *lock.version.get_mut() = metadata.version;
+        lock.hold_warn = metadata.hold_warn;
+        #[cfg(feature = "profile")]
+        {
+            lock.profile = metadata.profile;
+        }
+        #[cfg(feature = "fairness")]
+        {
+            lock.fairness = metadata.fairness;
+        }
+        lock.trace = metadata.trace;
+        lock.sites = metadata.sites;
+        lock.timeouts = metadata.timeouts;
+        lock.deadlock = metadata.deadlock;
+        lock.priority = metadata.priority;
+        lock.backoff = metadata.backoff;
+        lock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parts_round_trip_keeps_the_stats() {
+        let lock = RwLock::builder(vec![1u32])
+            .label("jobs")
+            .site_stats_capacity(4)
+            .timeout_stats(Duration::ZERO)
+            .build()
+            .unwrap();
+        lock.super_safe_write(|jobs| jobs.push(2));
+        let writer = lock.write().unwrap();
+        assert!(lock.read_timeout(Duration::from_millis(1)).is_err());
+        writer.release();
+
+        let (jobs, metadata) = lock.into_parts();
+        assert_eq!(jobs, [1, 2]);
+        assert_eq!((metadata.label(), metadata.version()), (Some("jobs"), 2));
+        let lock = RwLock::from_parts(jobs, metadata);
+        assert_eq!(lock.label(), Some("jobs"));
+        assert_eq!(lock.version(), 2);
+        assert_eq!(lock.site_stats().len(), 2);
+        assert_eq!(lock.timeout_stats().timeouts, 1);
+        assert_eq!(lock.super_safe_read(|jobs| jobs.len()), 2);
+    }
+}
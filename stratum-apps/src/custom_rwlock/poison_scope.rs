@@ -0,0 +1,122 @@
+//! Scoped policy turning poisoned acquisitions into a single error, see [`run_poison_safe`].
+//!
+//! Applications usually handle a poisoned lock the same way wherever it happens, typically by
+//! failing the current request or task. Inside [`run_poison_safe`], acquiring a poisoned
+//! [`RwLock`] through `safe_read`, `safe_write`, `read`, `write`, `read_auto` or `write_auto`
+//! (and the helpers built on them) aborts the whole scope with a [`LockPoisoned`] error, so
+//! individual call sites do not have to handle [`PoisonError`](std::sync::PoisonError).
+//!
+//! The error is raised by unwinding, so the policy only applies with `panic = "unwind"`. With
+//! `panic = "abort"`, where escalating would abort the process, poisoned acquisitions are
+//! reported as usual even inside a scope, although no panic gets to poison a lock then anyway.
+
+use super::RwLock;
+use std::{
+    cell::Cell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::PoisonError,
+};
+
+thread_local! {
+    // Number of `run_poison_safe` scopes the current thread is in.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A lock acquired inside [`run_poison_safe`] was poisoned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockPoisoned {
+    lock: String,
+}
+
+impl LockPoisoned {
+    /// Identifier of the poisoned lock, its label if it has one.
+    pub fn lock(&self) -> &str {
+        &self.lock
+    }
+}
+
+impl fmt::Display for LockPoisoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lock poisoned: {}", self.lock)
+    }
+}
+
+impl std::error::Error for LockPoisoned {}
+
+// Leaves the scope, including while unwinding out of it.
+struct Scope;
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Runs `f`, returning a [`LockPoisoned`] error as soon as it acquires a poisoned lock.
+///
+/// The error unwinds out of `f` without running the panic hook, releasing the guards held on the
+/// way like a panic would. Write guards released this way poison their own locks, as with any
+/// unwinding. Other panics are propagated unchanged. The policy only applies to the current
+/// thread, threads spawned by `f` are not affected. Outside of a scope, poisoned acquisitions
+/// are reported as usual.
+pub fn run_poison_safe<F, R>(f: F) -> Result<R, LockPoisoned>
+where
+    F: FnOnce() -> R,
+{
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let scope = Scope;
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    drop(scope);
+    match result {
+        Ok(result) => Ok(result),
+        Err(payload) => match payload.downcast::<LockPoisoned>() {
+            Ok(poisoned) => Err(*poisoned),
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    // Called with the error of every poisoned acquisition, escalates it inside a scope.
+    pub(crate) fn poisoned<G>(&self, e: PoisonError<G>) -> PoisonError<G> {
+        if !cfg!(panic = "unwind") || DEPTH.with(Cell::get) == 0 {
+            return e;
+        }
+        // The std guard is released first, so unwinding does not hold the lock.
+        drop(e);
+        panic::resume_unwind(Box::new(LockPoisoned {
+            lock: self.describe(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poisoned_access_fails_the_scope() {
+        let lock = RwLock::builder(0u8).label("jobs").build().unwrap();
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            lock.super_safe_write(|_| panic!("poison the lock"));
+        }));
+
+        let mut reached = false;
+        let error = run_poison_safe(|| {
+            let value = lock.super_safe_read(|v| *v);
+            reached = true;
+            value
+        })
+        .unwrap_err();
+        assert!(!reached);
+        assert!(error.lock().starts_with("jobs "));
+
+        // Healthy locks and accesses outside of a scope behave as usual.
+        assert_eq!(
+            run_poison_safe(|| RwLock::new(1u8).super_safe_read(|v| *v)),
+            Ok(1)
+        );
+        assert!(lock.safe_read(|_| ()).is_err());
+    }
+}
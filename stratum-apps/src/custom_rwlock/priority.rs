@@ -0,0 +1,133 @@
+//! Detection of priority inversion: a high priority writer kept waiting by readers.
+//!
+//! Priority inheritance needs support from the OS scheduler that the std lock does not expose,
+//! so locks built with
+//! [`RwLockBuilder::priority_tracking`](super::RwLockBuilder::priority_tracking) only detect and
+//! count inversions. Readers can give way at safe points with
+//! [`YieldingReader::yield_if_priority_blocked`](super::YieldingReader::yield_if_priority_blocked).
+
+use super::{RwLock, WriteGuard};
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    PoisonError, RwLockWriteGuard,
+};
+
+/// Readers and waiting priority writers of a lock built with priority tracking.
+#[derive(Debug, Default)]
+pub(crate) struct PriorityTracking {
+    readers: AtomicUsize,
+    waiting_writers: AtomicUsize,
+    inversions: AtomicU64,
+}
+
+impl PriorityTracking {
+    pub(crate) fn read_acquired(&self) {
+        self.readers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn read_released(&self) {
+        self.readers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn waiting_writers(&self) -> usize {
+        self.waiting_writers.load(Ordering::Relaxed)
+    }
+}
+
+// Unregisters a waiting priority writer, including while unwinding out of the acquisition.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquires an exclusive lock on behalf of a high priority writer.
+    ///
+    /// Same as [`RwLock::write`], except that on a lock built with priority tracking, the wait
+    /// is visible through [`RwLock::waiting_writer_priority_blocked`], and counted in
+    /// [`RwLock::priority_inversions`] if readers hold the lock when it starts.
+    #[track_caller]
+    pub fn write_priority(
+        &self,
+    ) -> Result<WriteGuard<'_, T>, PoisonError<RwLockWriteGuard<'_, T>>> {
+        let Some(tracking) = &self.priority else {
+            return self.write();
+        };
+        self.debug_assert_writable();
+        if let Ok(guard) = self.inner.try_write() {
+            return Ok(WriteGuard::new(self, guard));
+        }
+        tracking.waiting_writers.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&tracking.waiting_writers);
+        if tracking.readers.load(Ordering::Relaxed) > 0 {
+            tracking.inversions.fetch_add(1, Ordering::Relaxed);
+        }
+        self.write()
+    }
+
+    /// Returns whether a priority writer is currently waiting while readers hold the lock.
+    ///
+    /// Always `false` on a lock built without priority tracking. The answer is only a snapshot
+    /// and may be stale by the time the caller acts on it.
+    pub fn waiting_writer_priority_blocked(&self) -> bool {
+        self.priority.as_ref().is_some_and(|tracking| {
+            tracking.waiting_writers.load(Ordering::Relaxed) > 0
+                && tracking.readers.load(Ordering::Relaxed) > 0
+        })
+    }
+
+    /// Number of priority writers that found the lock held by readers so far.
+    pub fn priority_inversions(&self) -> u64 {
+        self.priority
+            .as_ref()
+            .map_or(0, |tracking| tracking.inversions.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition not reached");
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_inversion_is_flagged_while_the_writer_waits() {
+        let lock = RwLock::builder(0u32).priority_tracking().build().unwrap();
+        thread::scope(|s| {
+            let reader = lock.read().unwrap();
+            let writer = s.spawn(|| lock.write_priority().unwrap().release());
+            wait_until(|| lock.waiting_writer_priority_blocked());
+            reader.release();
+            writer.join().unwrap();
+        });
+        assert!(!lock.waiting_writer_priority_blocked());
+        assert_eq!(lock.priority_inversions(), 1);
+
+        // Readers at a safe point only give way while a priority writer is blocked.
+        let yielded = thread::scope(|s| {
+            lock.read_yielding(|reader| {
+                assert!(!reader.yield_if_priority_blocked().unwrap());
+                s.spawn(|| lock.write_priority().unwrap().release());
+                wait_until(|| lock.waiting_writer_priority_blocked());
+                reader.yield_if_priority_blocked().unwrap()
+            })
+            .unwrap()
+        });
+        assert!(yielded);
+        assert_eq!(lock.priority_inversions(), 2);
+        assert!(!RwLock::new(0u8).waiting_writer_priority_blocked());
+    }
+}
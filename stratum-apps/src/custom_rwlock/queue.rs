@@ -0,0 +1,78 @@
+//! Batched access to work queues stored as `RwLock<VecDeque<T>>`.
+//!
+//! Producers and consumers moving items one at a time pay for one lock acquisition per item.
+//! These move whole batches under a single write lock instead.
+
+use super::RwLock;
+use std::{
+    collections::VecDeque,
+    sync::{PoisonError, RwLockWriteGuard},
+};
+
+impl<T> RwLock<VecDeque<T>> {
+    /// Pops up to `n` items from the front of the queue, under a single write lock.
+    ///
+    /// The items are returned in queue order. Fewer than `n` are returned if the queue runs out,
+    /// in which case it is left empty.
+    #[track_caller]
+    pub fn drain_n(
+        &self,
+        n: usize,
+    ) -> Result<Vec<T>, PoisonError<RwLockWriteGuard<'_, VecDeque<T>>>> {
+        self.safe_write(|queue| {
+            let n = n.min(queue.len());
+            queue.drain(..n).collect()
+        })
+    }
+
+    /// Pushes every item to the back of the queue, under a single write lock.
+    ///
+    /// The lock is held while `items` is iterated, so it should be a ready collection rather than
+    /// an iterator doing expensive work.
+    #[track_caller]
+    pub fn push_all<I>(
+        &self,
+        items: I,
+    ) -> Result<(), PoisonError<RwLockWriteGuard<'_, VecDeque<T>>>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.safe_write(|queue| queue.extend(items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_drain_n_takes_at_most_n_items() {
+        let queue = RwLock::new(VecDeque::new());
+        let mut drained = thread::scope(|s| {
+            for producer in 0..4 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for batch in 0..50 {
+                        let base = (producer * 50 + batch) * 10;
+                        queue.push_all(base..base + 10).unwrap();
+                    }
+                });
+            }
+            let mut drained = Vec::new();
+            while drained.len() < 2000 {
+                let batch = queue.drain_n(7).unwrap();
+                assert!(batch.len() <= 7);
+                drained.extend(batch);
+            }
+            drained
+        });
+        assert!(queue.drain_n(7).unwrap().is_empty());
+        drained.sort_unstable();
+        assert_eq!(drained, (0..2000).collect::<Vec<_>>());
+
+        queue.push_all([1, 2, 3]).unwrap();
+        assert_eq!(queue.drain_n(2).unwrap(), vec![1, 2]);
+        assert_eq!(queue.super_safe_read(|q| q.clone()), VecDeque::from([3]));
+    }
+}
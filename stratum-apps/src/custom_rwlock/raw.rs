@@ -0,0 +1,131 @@
+//! [`lock_api::RawRwLock`] implementation, available with the `lock_api` feature.
+//!
+//! Lets code that is generic over `lock_api` use a lock from this crate, e.g.
+//! `lock_api::RwLock<RawRwLock, T>`.
+//!
+//! `lock_api` raw locks have no notion of poisoning, so unlike [`super::RwLock`] a panic while
+//! holding a `lock_api` guard leaves the lock usable. New readers wait while a writer is queued,
+//! so writers are not starved by a steady stream of readers.
+
+use std::sync::{Condvar, Mutex as Mutex_, MutexGuard, PoisonError};
+
+struct State {
+    readers: usize,
+    writer: bool,
+    waiting_writers: usize,
+}
+
+/// Raw read-write lock usable with [`lock_api::RwLock`].
+pub struct RawRwLock {
+    state: Mutex_<State>,
+    changed: Condvar,
+}
+
+impl RawRwLock {
+    // The internal mutex is only held for a few instructions and never while running user code,
+    // so it cannot be poisoned by a panicking lock user.
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+unsafe impl lock_api::RawRwLock for RawRwLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawRwLock {
+        state: Mutex_::new(State {
+            readers: 0,
+            writer: false,
+            waiting_writers: 0,
+        }),
+        changed: Condvar::new(),
+    };
+
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock_shared(&self) {
+        let state = self.state();
+        let mut state = self
+            .changed
+            .wait_while(state, |s| s.writer || s.waiting_writers > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.readers += 1;
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        let mut state = self.state();
+        if state.writer || state.waiting_writers > 0 {
+            return false;
+        }
+        state.readers += 1;
+        true
+    }
+
+    unsafe fn unlock_shared(&self) {
+        let mut state = self.state();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.changed.notify_all();
+        }
+    }
+
+    fn lock_exclusive(&self) {
+        let mut state = self.state();
+        state.waiting_writers += 1;
+        let mut state = self
+            .changed
+            .wait_while(state, |s| s.writer || s.readers > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.waiting_writers -= 1;
+        state.writer = true;
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        let mut state = self.state();
+        if state.writer || state.readers > 0 {
+            return false;
+        }
+        state.writer = true;
+        true
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.state().writer = false;
+        self.changed.notify_all();
+    }
+
+    fn is_locked(&self) -> bool {
+        let state = self.state();
+        state.writer || state.readers > 0
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.state().writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_lock_api_rwlock_read_write() {
+        let lock = Arc::new(lock_api::RwLock::<RawRwLock, Vec<u32>>::new(Vec::new()));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let lock = lock.clone();
+                thread::spawn(move || lock.write().push(i))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let first = lock.read();
+        let second = lock.read();
+        assert_eq!(first.len(), 4);
+        assert_eq!(second.len(), 4);
+        assert!(lock.try_write().is_none());
+        drop((first, second));
+        assert!(lock.try_write().is_some());
+    }
+}
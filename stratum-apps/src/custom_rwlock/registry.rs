@@ -0,0 +1,148 @@
+//! Process wide registry of the guards currently held, available with the `test-util` feature.
+//!
+//! Every acquisition of every [`RwLock`](super::RwLock) is recorded from the acquisition hooks
+//! until it is released, with the label of its lock, its acquisition site and its thread.
+//! Concurrency tests can then check lock topology invariants, e.g. that a lock is never acquired
+//! while another one is held, by inspecting [`held_guards`] right after an acquisition.
+//!
+//! Recording takes a single process wide mutex for a short insertion or removal, and never
+//! allocates beyond growing the registry, so workloads keep roughly their timing.
+
+use std::{
+    collections::HashMap,
+    panic::Location,
+    sync::{Mutex as Mutex_, MutexGuard, OnceLock, PoisonError},
+    thread::{self, ThreadId},
+};
+
+/// A guard currently held, as recorded by the registry, see [`held_guards`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldGuard {
+    label: Option<&'static str>,
+    lock: usize,
+    write: bool,
+    site: &'static Location<'static>,
+    thread: ThreadId,
+}
+
+impl HeldGuard {
+    /// Label of the lock, if it has one.
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
+    }
+
+    /// Address of the lock, see [`RwLock::addr`](super::RwLock::addr).
+    pub fn lock(&self) -> usize {
+        self.lock
+    }
+
+    /// Whether the lock is held for writing.
+    pub fn is_write(&self) -> bool {
+        self.write
+    }
+
+    /// Where the guard was acquired.
+    pub fn site(&self) -> &'static Location<'static> {
+        self.site
+    }
+
+    /// Thread holding the guard.
+    pub fn thread(&self) -> ThreadId {
+        self.thread
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    held: HashMap<u64, HeldGuard>,
+}
+
+// Only touched for short insertions and removals, poisoning cannot leave it torn.
+fn registry() -> MutexGuard<'static, Registry> {
+    static REGISTRY: OnceLock<Mutex_<Registry>> = OnceLock::new();
+    REGISTRY
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Registration of one acquisition, removed when dropped.
+pub(crate) struct Registered(u64);
+
+impl Registered {
+    pub(crate) fn new(
+        label: Option<&'static str>,
+        lock: usize,
+        write: bool,
+        site: &'static Location<'static>,
+    ) -> Self {
+        let guard = HeldGuard {
+            label,
+            lock,
+            write,
+            site,
+            thread: thread::current().id(),
+        };
+        let mut registry = registry();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.held.insert(id, guard);
+        Registered(id)
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        registry().held.remove(&self.0);
+    }
+}
+
+/// Returns every guard currently held, on any lock and thread, in acquisition order.
+///
+/// Closure-based accessors such as `safe_read` are included while their closure runs. Guards of
+/// other threads may be acquired or released right after the snapshot is taken, only the guards
+/// of the calling thread are guaranteed to be up to date.
+pub fn held_guards() -> Vec<HeldGuard> {
+    let registry = registry();
+    let mut held: Vec<_> = registry
+        .held
+        .iter()
+        .map(|(&id, &guard)| (id, guard))
+        .collect();
+    drop(registry);
+    held.sort_by_key(|&(id, _)| id);
+    held.into_iter().map(|(_, guard)| guard).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_rwlock::RwLock;
+
+    #[test]
+    fn test_registry_tracks_held_guards() {
+        let (a, b) = (
+            RwLock::builder(0u8).label("a").build().unwrap(),
+            RwLock::builder(0u8).label("b").build().unwrap(),
+        );
+        let mine = || {
+            held_guards()
+                .into_iter()
+                .filter(|guard| guard.thread() == thread::current().id())
+                .collect::<Vec<_>>()
+        };
+        let read = a.read().unwrap();
+        b.safe_write(|_| {
+            let held = mine();
+            assert_eq!(held.len(), 2);
+            assert_eq!((held[0].label(), held[0].is_write()), (Some("a"), false));
+            assert_eq!((held[1].label(), held[1].is_write()), (Some("b"), true));
+            assert_eq!(held[1].lock(), b.addr());
+            assert_eq!(held[0].site().file(), file!());
+        })
+        .unwrap();
+        read.release();
+        assert!(mine().is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+//! Human-readable dump of the state of a set of locks, for debug endpoints, and the `Debug`
+//! implementation of [`RwLock`].
+
+use super::RwLock;
+use std::{
+    fmt::{self, Write},
+    sync::atomic::Ordering,
+    sync::TryLockError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Held {
+    Free,
+    Read,
+    Write,
+}
+
+// Probes the lock with non-blocking attempts released right away. A poisoned lock is still
+// acquired by the probe, so it is told apart like any other.
+fn probe<T: ?Sized>(lock: &RwLock<T>) -> Held {
+    match lock.inner.try_write() {
+        Ok(_) | Err(TryLockError::Poisoned(_)) => Held::Free,
+        Err(TryLockError::WouldBlock) => match lock.inner.try_read() {
+            Ok(_) | Err(TryLockError::Poisoned(_)) => Held::Read,
+            Err(TryLockError::WouldBlock) => Held::Write,
+        },
+    }
+}
+
+/// Describes the current state of every lock of `locks`, one line per lock.
+///
+/// Each line gives the lock label (or type and address), whether it is free, read-held or
+/// write-held, the number of outstanding acquisitions, the poison flag and the number of writes
+/// so far. In debug builds, the thread holding the write lock is also reported, as the index
+/// that thread gets in these reports.
+///
+/// Gathering the report never blocks: locks are only probed with non-blocking attempts that are
+/// released right away, everything else is read from atomics.
+pub fn debug_lock_report<T: ?Sized>(locks: &[&RwLock<T>]) -> String {
+    let mut report = String::new();
+    for lock in locks {
+        let state = match probe(lock) {
+            Held::Free => "free",
+            Held::Read => "read-held",
+            Held::Write => "write-held",
+        };
+        let _ = write!(report, "{}: {state}", lock.describe());
+        #[cfg(debug_assertions)]
+        match lock.writer.load(Ordering::Relaxed) {
+            0 => {}
+            thread => {
+                let _ = write!(report, " by thread {thread}");
+            }
+        }
+        let _ = writeln!(
+            report,
+            ", {} outstanding, poisoned: {}, {} writes",
+            lock.outstanding_guards(),
+            lock.is_poisoned(),
+            lock.version.load(Ordering::Relaxed)
+        );
+    }
+    report
+}
+
+// A count that could not be determined is shown as `?`.
+struct Count(Option<usize>);
+
+impl fmt::Debug for Count {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(count) => write!(f, "{count}"),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+enum State {
+    Free,
+    Read(Count),
+    Write,
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Free => write!(f, "Free"),
+            Self::Read(readers) => write!(f, "Read({readers:?})"),
+            Self::Write => write!(f, "Write"),
+        }
+    }
+}
+
+/// `{:?}` shows the label and the value, if the lock is immediately available for reading.
+///
+/// `{:#?}` shows the state of the lock instead: its label, whether it is poisoned, whether it is
+/// free, read-held with the number of readers, or write-held, and the number of waiting writers.
+/// Formatting never blocks, counts that cannot be determined are shown as `?`. Only locks built
+/// with [`RwLockBuilder::priority_tracking`](super::RwLockBuilder::priority_tracking) know about
+/// waiting writers, and only about those acquiring with [`RwLock::write_priority`].
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            return f
+                .debug_struct("RwLock")
+                .field("label", &self.label)
+                .field("inner", &&self.inner)
+                .finish_non_exhaustive();
+        }
+        let state = match probe(self) {
+            Held::Free => State::Free,
+            // The counter may lag behind the probe, a reader would then be missing from it.
+            Held::Read => State::Read(Count(Some(self.outstanding_guards()).filter(|&n| n > 0))),
+            Held::Write => State::Write,
+        };
+        let waiters = self
+            .priority
+            .as_ref()
+            .map(|tracking| tracking.waiting_writers());
+        f.debug_struct("RwLock")
+            .field("label", &self.label)
+            .field("poisoned", &self.is_poisoned())
+            .field("state", &state)
+            .field("waiters", &Count(waiters))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{mpsc, Barrier},
+        thread,
+    };
+
+    #[test]
+    fn test_report_shows_holder_and_poison() {
+        let held = RwLock::builder(0u8).label("held").build().unwrap();
+        let poisoned = RwLock::builder(0u8).label("poisoned").build().unwrap();
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            poisoned.super_safe_write(|_| panic!("poison the lock"));
+        }));
+
+        let (holding, done) = (Barrier::new(2), Barrier::new(2));
+        let (tx, rx) = mpsc::channel();
+        let report = thread::scope(|s| {
+            s.spawn(|| {
+                let guard = held.write().unwrap();
+                #[cfg(debug_assertions)]
+                tx.send(super::super::holders::current_thread_index())
+                    .unwrap();
+                holding.wait();
+                done.wait();
+                guard.release();
+            });
+            holding.wait();
+            let report = debug_lock_report(&[&held, &poisoned]);
+            done.wait();
+            report
+        });
+        drop(tx);
+
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines[0].starts_with("held (RwLock<u8>@"));
+        assert!(lines[0].contains("write-held"));
+        assert!(lines[0].contains("1 outstanding"));
+        if let Ok(holder) = rx.try_recv() {
+            assert!(lines[0].contains(&format!("write-held by thread {holder},")));
+        }
+        assert!(lines[1].starts_with("poisoned (RwLock<u8>@"));
+        assert!(lines[1].contains(": free, 0 outstanding, poisoned: true"));
+    }
+
+    #[test]
+    fn test_alternate_debug_shows_the_state() {
+        let lock = RwLock::builder(vec![1u8]).label("jobs").build().unwrap();
+        let free = format!("{lock:#?}");
+        assert!(free.contains("label: Some(\n        \"jobs\",\n    ),"));
+        assert!(free.contains("state: Free,"));
+        assert!(free.contains("waiters: ?,"));
+
+        let (first, second) = (lock.read().unwrap(), lock.read().unwrap());
+        let read = format!("{lock:#?}");
+        assert!(read.contains("state: Read(2),"));
+        assert!(read.contains("poisoned: false,"));
+        first.release();
+        second.release();
+
+        // The compact format still shows the value.
+        assert!(format!("{lock:?}").contains("data: [1]"));
+        let tracked = RwLock::builder(0u8).priority_tracking().build().unwrap();
+        assert!(format!("{tracked:#?}").contains("waiters: 0,"));
+    }
+}
@@ -0,0 +1,256 @@
+//! Bounded retries of optimistic updates.
+//!
+//! [`RwLock::retry_write`] runs a closure under the write lock that can decide to abort and be
+//! retried, e.g. when it depends on some state outside of the lock that is not ready yet. The
+//! lock is released between attempts, which are spaced according to a [`Backoff`].
+//!
+//! Locks built with [`RwLockBuilder::backoff`](super::RwLockBuilder::backoff) use their own
+//! strategy instead of the default [`ExponentialBackoff`], in `retry_write` and in every timed
+//! acquisition.
+
+use super::RwLock;
+use std::{
+    fmt,
+    panic::{RefUnwindSafe, UnwindSafe},
+    sync::{Arc, PoisonError, RwLockWriteGuard},
+    thread,
+    time::Duration,
+};
+
+/// Returned by a [`RwLock::retry_write`] closure to release the lock and be called again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry;
+
+/// Strategy spacing consecutive attempts.
+pub trait Backoff {
+    /// Pause before the retry following failed attempt number `attempt`, counted from 1.
+    fn delay(&self, attempt: u32) -> Duration;
+}
+
+/// Doubles the pause after every failed attempt, from `initial` up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExponentialBackoff {
+    /// Pause after the first failed attempt.
+    pub initial: Duration,
+    /// Longest pause.
+    pub max: Duration,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        ExponentialBackoff {
+            initial: Duration::from_micros(1),
+            max: Duration::from_millis(1),
+        }
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// Creates the backoff of every retry loop on a lock, see
+/// [`RwLockBuilder::backoff`](super::RwLockBuilder::backoff).
+#[derive(Clone)]
+pub(crate) struct NewBackoff(
+    pub(crate) Arc<dyn Fn() -> Box<dyn Backoff> + Send + Sync + UnwindSafe + RefUnwindSafe>,
+);
+
+impl fmt::Debug for NewBackoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NewBackoff(..)")
+    }
+}
+
+// Backoff of one retry loop, the default one unless the lock was built with its own.
+pub(crate) enum LockBackoff {
+    Default(ExponentialBackoff),
+    Custom(Box<dyn Backoff>),
+}
+
+impl Backoff for LockBackoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        match self {
+            Self::Default(backoff) => backoff.delay(attempt),
+            Self::Custom(backoff) => backoff.delay(attempt),
+        }
+    }
+}
+
+/// Error returned by [`RwLock::retry_write`], generic over the std guard of the poisoned case.
+pub enum RetryError<G> {
+    /// Every attempt asked to be retried.
+    Exhausted,
+    /// The lock is poisoned. The std guard can be recovered with [`PoisonError::into_inner`].
+    Poisoned(PoisonError<G>),
+}
+
+impl<G> fmt::Debug for RetryError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exhausted => write!(f, "Exhausted"),
+            Self::Poisoned(_) => write!(f, "Poisoned(..)"),
+        }
+    }
+}
+
+impl<G> fmt::Display for RetryError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exhausted => write!(f, "Retry attempts exhausted"),
+            Self::Poisoned(_) => write!(f, "Lock poisoned"),
+        }
+    }
+}
+
+impl<G> std::error::Error for RetryError<G> {}
+
+impl<G> From<PoisonError<G>> for RetryError<G> {
+    fn from(e: PoisonError<G>) -> Self {
+        RetryError::Poisoned(e)
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Calls `f` under the write lock until it stops returning [`Retry`], at most `max_attempts`
+    /// times, with the backoff of the lock between attempts, an [`ExponentialBackoff`] unless
+    /// configured otherwise.
+    ///
+    /// `f` runs at least once, even if `max_attempts` is zero. Changes it made before returning
+    /// [`Retry`] are kept, so it should only ask for a retry before touching the value.
+    ///
+    /// The lock is released between attempts, so that other threads can change the value
+    /// meanwhile. Every attempt is therefore a write of its own, which bumps the
+    /// [`RwLock::version`] even if it only asked for a retry, so results memoized with
+    /// [`RwLock::cached_read`] are computed again after a retried write.
+    #[track_caller]
+    pub fn retry_write<F, R>(
+        &self,
+        max_attempts: u32,
+        f: F,
+    ) -> Result<R, RetryError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnMut(&mut T) -> Result<R, Retry>,
+    {
+        self.retry_write_with_backoff(max_attempts, self.backoff(), f)
+    }
+
+    /// Same as [`RwLock::retry_write`], spacing attempts according to `backoff`.
+    #[track_caller]
+    pub fn retry_write_with_backoff<F, R, B>(
+        &self,
+        max_attempts: u32,
+        backoff: B,
+        mut f: F,
+    ) -> Result<R, RetryError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnMut(&mut T) -> Result<R, Retry>,
+        B: Backoff,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.safe_write(&mut f)? {
+                Ok(result) => return Ok(result),
+                Err(Retry) if attempt >= max_attempts => return Err(RetryError::Exhausted),
+                Err(Retry) => {
+                    thread::sleep(backoff.delay(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    // Starts a retry loop, with a fresh backoff from the factory configured on the lock, if any.
+    pub(crate) fn backoff(&self) -> LockBackoff {
+        match &self.backoff {
+            Some(new_backoff) => LockBackoff::Custom((new_backoff.0)()),
+            None => LockBackoff::Default(ExponentialBackoff::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_retry_write_until_success_or_exhaustion() {
+        let lock = RwLock::new(0u32);
+        let mut calls = 0;
+        let result = lock.retry_write(5, |v| {
+            calls += 1;
+            if calls < 3 {
+                return Err(Retry);
+            }
+            *v = calls;
+            Ok(calls)
+        });
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(lock.super_safe_read(|v| *v), 3);
+
+        let mut calls = 0;
+        let result = lock.retry_write(2, |_| -> Result<(), Retry> {
+            calls += 1;
+            Err(Retry)
+        });
+        assert!(matches!(result, Err(RetryError::Exhausted)));
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_exponential_backoff_is_capped() {
+        let backoff = ExponentialBackoff::default();
+        assert_eq!(backoff.delay(1), Duration::from_micros(1));
+        assert_eq!(backoff.delay(4), Duration::from_micros(8));
+        assert_eq!(backoff.delay(40), Duration::from_millis(1));
+    }
+
+    struct CountingBackoff(Arc<AtomicU32>);
+
+    impl Backoff for CountingBackoff {
+        fn delay(&self, _attempt: u32) -> Duration {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Duration::from_millis(1)
+        }
+    }
+
+    #[test]
+    fn test_configured_backoff_spaces_every_retry_loop() {
+        let (delays, loops) = (Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0)));
+        let (counted, started) = (delays.clone(), loops.clone());
+        let lock = RwLock::builder(0u32)
+            .backoff(move || {
+                started.fetch_add(1, Ordering::Relaxed);
+                CountingBackoff(counted.clone())
+            })
+            .build()
+            .unwrap();
+
+        // A contended timed acquisition.
+        let held = std::sync::Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let writer = lock.write().unwrap();
+                held.wait();
+                thread::sleep(Duration::from_millis(20));
+                writer.release();
+            });
+            held.wait();
+            lock.read_timeout(Duration::from_secs(5)).unwrap().release();
+        });
+        assert_eq!(loops.load(Ordering::Relaxed), 1);
+        assert!(delays.load(Ordering::Relaxed) > 0);
+
+        let before = delays.load(Ordering::Relaxed);
+        let result = lock.retry_write(3, |_| -> Result<(), Retry> { Err(Retry) });
+        assert!(matches!(result, Err(RetryError::Exhausted)));
+        assert_eq!(loops.load(Ordering::Relaxed), 2);
+        assert_eq!(delays.load(Ordering::Relaxed) - before, 2);
+    }
+}
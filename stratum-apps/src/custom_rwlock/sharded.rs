@@ -0,0 +1,237 @@
+//! A value split into independently locked shards.
+
+use super::{with_all_read, with_all_write, AutoWriteGuard, ReadGuard, RwLock, WriteGuard};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::PoisonError,
+    thread,
+};
+
+/// A fixed set of [`RwLock`] shards, each protecting its own part of the state.
+///
+/// Writers to different shards never contend. [`ShardedRwLock::read_all`] and
+/// [`ShardedRwLock::write_all`] lock every shard in the same canonical order, so they cannot
+/// deadlock against each other nor against writers holding a single shard.
+#[derive(Debug)]
+pub struct ShardedRwLock<T> {
+    shards: Box<[RwLock<T>]>,
+}
+
+impl<T> ShardedRwLock<T> {
+    /// Creates `count` shards, the value of each one built by `init` from its index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero.
+    pub fn new<F>(count: usize, init: F) -> Self
+    where
+        F: FnMut(usize) -> T,
+    {
+        assert!(count > 0, "a sharded lock needs at least one shard");
+        ShardedRwLock {
+            shards: (0..count).map(init).map(RwLock::new).collect(),
+        }
+    }
+
+    /// Number of shards.
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Always `false`: there is at least one shard.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Returns the shard at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn shard(&self, index: usize) -> &RwLock<T> {
+        &self.shards[index]
+    }
+
+    /// Returns the shard `key` maps to.
+    pub fn shard_for<K: Hash + ?Sized>(&self, key: &K) -> &RwLock<T> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+
+    /// Gives `f` a consistent view across all shards.
+    ///
+    /// Every shard is locked for reading, in canonical order, before `f` runs and stays locked
+    /// until it returns, so no writer can interleave with the global read. The guards are passed
+    /// in shard order.
+    pub fn read_all<F, R>(&self, f: F) -> Result<R, PoisonError<()>>
+    where
+        F: FnOnce(&[ReadGuard<'_, T>]) -> R,
+    {
+        with_all_read(&self.shards, f)
+    }
+
+    /// Exclusive counterpart of [`ShardedRwLock::read_all`], for updates that span every shard.
+    pub fn write_all<F, R>(&self, f: F) -> Result<R, PoisonError<()>>
+    where
+        F: FnOnce(&mut [WriteGuard<'_, T>]) -> R,
+    {
+        with_all_write(&self.shards, f)
+    }
+
+    /// Runs `f` over every shard in parallel, each shard locked for writing on its own scoped
+    /// thread, and returns once all of them are done.
+    ///
+    /// Meant for periodic maintenance such as evicting expired entries. Every worker holds a
+    /// single shard, so this cannot deadlock against other users of the lock. If `f` panics, the
+    /// other workers still run to completion and every shard is released before the panic is
+    /// propagated, the shard `f` panicked on being left poisoned. A poisoned shard is skipped and
+    /// reported once every worker is done.
+    pub fn par_for_each<F>(&self, f: F) -> Result<(), PoisonError<()>>
+    where
+        T: Send + Sync,
+        F: Fn(&mut T) + Sync,
+    {
+        let f = &f;
+        let healthy = thread::scope(|s| {
+            let workers: Vec<_> = self
+                .shards
+                .iter()
+                .map(|shard| s.spawn(move || shard.safe_write(f).is_ok()))
+                .collect();
+            // Join every worker, even past a poisoned shard, so all of them are done on return.
+            let mut healthy = true;
+            for worker in workers {
+                healthy &= worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            }
+            healthy
+        });
+        if healthy {
+            Ok(())
+        } else {
+            Err(PoisonError::new(()))
+        }
+    }
+
+    /// Runs `f` with the index and value of every shard that can be locked for writing right
+    /// away, skipping the others, and returns how many shards were processed.
+    ///
+    /// Meant for best-effort sweeps that should not wait on a hot shard. Poisoned shards are
+    /// skipped too.
+    #[track_caller]
+    pub fn try_write_each<F>(&self, mut f: F) -> usize
+    where
+        F: FnMut(usize, &mut T),
+    {
+        let mut processed = 0;
+        for (i, shard) in self.shards.iter().enumerate() {
+            shard.debug_assert_writable();
+            if let Ok(inner) = shard.inner.try_write() {
+                f(i, &mut AutoWriteGuard::new(shard, inner));
+                processed += 1;
+            }
+        }
+        processed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::Barrier,
+    };
+
+    #[test]
+    fn test_read_all_total_matches_writes() {
+        let sharded = ShardedRwLock::new(8, |_| 0u64);
+        for i in 0..8 {
+            sharded.shard(i).super_safe_write(|v| *v = i as u64 * 10);
+        }
+        sharded.shard_for("key").super_safe_write(|v| *v += 1);
+        let total = sharded
+            .read_all(|guards| guards.iter().map(|guard| **guard).sum::<u64>())
+            .unwrap();
+        assert_eq!(total, (0..8).map(|i| i * 10).sum::<u64>() + 1);
+    }
+
+    #[test]
+    fn test_read_all_never_sees_a_partial_write_all() {
+        let sharded = ShardedRwLock::new(4, |_| 0u64);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..200 {
+                    sharded
+                        .write_all(|guards| guards.iter_mut().for_each(|guard| **guard += 1))
+                        .unwrap();
+                }
+            });
+            // A single shard writer, which must not deadlock with the global read and write.
+            s.spawn(|| {
+                for _ in 0..200 {
+                    sharded.shard(2).write().unwrap().release();
+                }
+            });
+            for _ in 0..200 {
+                let values = sharded
+                    .read_all(|guards| guards.iter().map(|guard| **guard).collect::<Vec<_>>())
+                    .unwrap();
+                assert!(values.iter().all(|&v| v == values[0]));
+            }
+        });
+    }
+
+    #[test]
+    fn test_par_for_each_mutates_every_shard_once() {
+        let sharded = ShardedRwLock::new(8, |i| vec![i]);
+        sharded
+            .par_for_each(|shard| shard.push(shard[0] * 10))
+            .unwrap();
+        for i in 0..8 {
+            assert_eq!(
+                sharded.shard(i).super_safe_read(|v| v.clone()),
+                vec![i, i * 10]
+            );
+        }
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            sharded
+                .par_for_each(|shard| assert_ne!(shard[0], 3))
+                .unwrap();
+        }));
+        assert!(result.is_err());
+        // Every shard was released, only the one the closure panicked on is poisoned.
+        for i in 0..8 {
+            assert_eq!(sharded.shard(i).safe_write(|_| ()).is_err(), i == 3);
+        }
+    }
+
+    #[test]
+    fn test_try_write_each_skips_busy_shards() {
+        let sharded = ShardedRwLock::new(4, |_| 0u32);
+        let (held, done) = (Barrier::new(2), Barrier::new(2));
+        thread::scope(|s| {
+            s.spawn(|| {
+                let guard = sharded.shard(1).write().unwrap();
+                held.wait();
+                done.wait();
+                guard.release();
+            });
+            held.wait();
+            let mut visited = Vec::new();
+            let processed = sharded.try_write_each(|i, shard| {
+                visited.push(i);
+                *shard += 1;
+            });
+            done.wait();
+            assert_eq!(processed, sharded.len() - 1);
+            assert_eq!(visited, [0, 2, 3]);
+        });
+        assert_eq!(sharded.shard(1).super_safe_read(|v| *v), 0);
+        assert_eq!(sharded.try_write_each(|_, _| ()), 4);
+    }
+}
@@ -0,0 +1,253 @@
+//! Single-flight computation of cache entries stored behind a [`RwLock`].
+//!
+//! When a cache miss triggers an expensive fill, every concurrent caller missing the same key
+//! would otherwise do the work. With [`RwLock::compute_once`] the first caller for a key leaves a
+//! pending [`FlightSlot`] in the map, computes the value without holding the lock, and publishes
+//! it. Later callers for the same key wait on the slot instead of recomputing.
+//!
+//! A single lazily computed value shared as `RwLock<Option<Arc<T>>>` is covered by
+//! [`RwLock::get_or_compute_arc`].
+
+use super::RwLock;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex as Mutex_, PoisonError},
+};
+
+enum State<V> {
+    Pending,
+    Ready(V),
+    // The computing caller panicked, waiters have to retry.
+    Abandoned,
+}
+
+struct Shared<V> {
+    state: Mutex_<State<V>>,
+    changed: Condvar,
+}
+
+/// Cache entry used by [`RwLock::compute_once`].
+///
+/// A slot is either pending, while its value is being computed, or ready.
+pub struct FlightSlot<V>(Arc<Shared<V>>);
+
+impl<V> Clone for FlightSlot<V> {
+    fn clone(&self) -> Self {
+        FlightSlot(self.0.clone())
+    }
+}
+
+impl<V: Clone> FlightSlot<V> {
+    /// Creates a slot that already holds `value`.
+    pub fn ready(value: V) -> Self {
+        Self::with_state(State::Ready(value))
+    }
+
+    /// Returns the value if it has been computed already.
+    pub fn get(&self) -> Option<V> {
+        match &*self.0.state.lock().unwrap_or_else(PoisonError::into_inner) {
+            State::Ready(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn with_state(state: State<V>) -> Self {
+        FlightSlot(Arc::new(Shared {
+            state: Mutex_::new(state),
+            changed: Condvar::new(),
+        }))
+    }
+
+    fn set(&self, state: State<V>) {
+        *self.0.state.lock().unwrap_or_else(PoisonError::into_inner) = state;
+        self.0.changed.notify_all();
+    }
+
+    // Blocks until the slot is no longer pending. `None` means the computation was abandoned.
+    fn wait(&self) -> Option<V> {
+        let state = self.0.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = self
+            .0
+            .changed
+            .wait_while(state, |state| matches!(state, State::Pending))
+            .unwrap_or_else(PoisonError::into_inner);
+        match &*state {
+            State::Ready(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Removes the pending slot and wakes the waiters if the computing caller unwinds.
+struct AbandonOnUnwind<'a, K: Eq + Hash, V: Clone> {
+    lock: &'a RwLock<HashMap<K, FlightSlot<V>>>,
+    key: Option<K>,
+    slot: FlightSlot<V>,
+}
+
+impl<K: Eq + Hash, V: Clone> Drop for AbandonOnUnwind<'_, K, V> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let _ = self.lock.safe_write(|map| map.remove(&key));
+            self.slot.set(State::Abandoned);
+        }
+    }
+}
+
+impl<K, V> RwLock<HashMap<K, FlightSlot<V>>>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Returns the value cached for `key`, computing it with `compute` if it is missing.
+    ///
+    /// Concurrent callers for the same key are coalesced: `compute` runs once, on the first
+    /// caller, and everyone else waits for its result. The lock itself is only held to look up or
+    /// insert the slot, never while computing, so other keys stay available. If `compute`
+    /// panics, the pending slot is removed and one of the waiters computes the value instead.
+    pub fn compute_once<F>(&self, key: K, compute: F) -> Result<V, PoisonError<()>>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        let mut compute = Some(compute);
+        loop {
+            let existing = self
+                .safe_read(|map| map.get(&key).cloned())
+                .map_err(|_| PoisonError::new(()))?;
+            let (slot, leader) = match existing {
+                Some(slot) => (slot, false),
+                None => self
+                    .safe_write(|map| match map.get(&key) {
+                        Some(slot) => (slot.clone(), false),
+                        None => {
+                            let slot = FlightSlot::with_state(State::Pending);
+                            map.insert(key.clone(), slot.clone());
+                            (slot, true)
+                        }
+                    })
+                    .map_err(|_| PoisonError::new(()))?,
+            };
+
+            if !leader {
+                match slot.wait() {
+                    Some(value) => return Ok(value),
+                    None => continue,
+                }
+            }
+
+            let mut abandon = AbandonOnUnwind {
+                lock: self,
+                key: Some(key),
+                slot,
+            };
+            let compute = compute
+                .take()
+                .expect("only the leader computes, and only once");
+            let value = compute(abandon.key.as_ref().expect("key is set until disarmed"));
+            abandon.key = None;
+            abandon.slot.set(State::Ready(value.clone()));
+            return Ok(value);
+        }
+    }
+}
+
+impl<T> RwLock<Option<Arc<T>>> {
+    /// Returns the shared value, computing it with `f` and storing it if the lock holds `None`.
+    ///
+    /// The common case, once the value is stored, only takes the shared lock to clone the `Arc`.
+    /// On a miss, the presence of the value is checked again under the exclusive lock, and `f`
+    /// runs while holding it: concurrent callers wait for that single computation and all get
+    /// the same `Arc`. If `f` panics, the lock is poisoned and nothing is stored.
+    #[track_caller]
+    pub fn get_or_compute_arc<F>(&self, f: F) -> Result<Arc<T>, PoisonError<()>>
+    where
+        F: FnOnce() -> T,
+    {
+        if let Some(value) = self
+            .safe_read(|value| value.clone())
+            .map_err(|_| PoisonError::new(()))?
+        {
+            return Ok(value);
+        }
+        self.safe_write(|value| value.get_or_insert_with(|| Arc::new(f())).clone())
+            .map_err(|_| PoisonError::new(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn test_compute_once_coalesces_callers() {
+        const CALLERS: usize = 8;
+        let cache: RwLock<HashMap<u32, FlightSlot<String>>> = RwLock::default();
+        let runs = AtomicUsize::new(0);
+        let barrier = Barrier::new(CALLERS);
+        let results: Vec<String> = thread::scope(|s| {
+            let handles: Vec<_> = (0..CALLERS)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        cache
+                            .compute_once(7, |key| {
+                                runs.fetch_add(1, Ordering::SeqCst);
+                                thread::sleep(Duration::from_millis(50));
+                                format!("value-{key}")
+                            })
+                            .unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|r| r == "value-7"));
+        let cached = cache.super_safe_read(|map| map[&7].get());
+        assert_eq!(cached.as_deref(), Some("value-7"));
+    }
+
+    #[test]
+    fn test_compute_once_retries_after_panic() {
+        let cache: RwLock<HashMap<u32, FlightSlot<u32>>> = RwLock::default();
+        let failed = std::panic::catch_unwind(|| cache.compute_once(1, |_| panic!("fill failed")));
+        assert!(failed.is_err());
+        assert_eq!(cache.compute_once(1, |k| k + 1).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_get_or_compute_arc_shares_one_value() {
+        const CALLERS: usize = 8;
+        let lock: RwLock<Option<Arc<String>>> = RwLock::default();
+        let runs = AtomicUsize::new(0);
+        let barrier = Barrier::new(CALLERS);
+        let results: Vec<Arc<String>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..CALLERS)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        lock.get_or_compute_arc(|| {
+                            runs.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(Duration::from_millis(20));
+                            String::from("config")
+                        })
+                        .unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        let stored = lock.super_safe_read(|value| value.clone()).unwrap();
+        assert!(results.iter().all(|r| Arc::ptr_eq(r, &stored)));
+    }
+}
@@ -0,0 +1,124 @@
+//! Per call site acquisition counts, enabled with
+//! [`RwLockBuilder::site_stats_capacity`](super::RwLockBuilder::site_stats_capacity).
+//!
+//! Unlike the hold profile, counting is cheap enough for release builds: sites live in a fixed
+//! table of atomic slots keyed by the address of their [`Location`], so recording an acquisition
+//! is a lock-free probe followed by a single relaxed increment, and never allocates.
+
+use super::RwLock;
+use std::{
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, Ordering},
+};
+
+#[derive(Debug)]
+struct Slot {
+    // Null until claimed by a site, never changed afterwards.
+    site: AtomicPtr<Location<'static>>,
+    count: AtomicU64,
+}
+
+#[derive(Debug)]
+pub(crate) struct SiteCounters {
+    slots: Box<[Slot]>,
+}
+
+impl SiteCounters {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        SiteCounters {
+            slots: (0..capacity)
+                .map(|_| Slot {
+                    site: AtomicPtr::new(ptr::null_mut()),
+                    count: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    /// Counts one acquisition from `site`, unless the table is full of other sites.
+    pub(crate) fn record(&self, site: &'static Location<'static>) {
+        let site = site as *const Location<'static> as *mut Location<'static>;
+        // Open addressing, starting from a slot derived from the address.
+        let start = (site as usize >> 3).wrapping_mul(0x9E37_79B9) % self.slots.len();
+        for i in 0..self.slots.len() {
+            let slot = &self.slots[(start + i) % self.slots.len()];
+            let mut claimed = slot.site.load(Ordering::Acquire);
+            if claimed.is_null() {
+                claimed = match slot.site.compare_exchange(
+                    ptr::null_mut(),
+                    site,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => site,
+                    Err(other) => other,
+                };
+            }
+            if claimed == site {
+                slot.count.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Returns the number of acquisitions of this lock from every call site, most frequent first.
+    ///
+    /// Empty unless the lock was built with a site stats capacity. Acquisitions from sites beyond
+    /// that capacity are not counted. Counts are relaxed, so concurrent acquisitions may or may
+    /// not be included.
+    pub fn site_stats(&self) -> Vec<(&'static Location<'static>, u64)> {
+        let Some(sites) = &self.sites else {
+            return Vec::new();
+        };
+        let mut stats: Vec<(&'static Location<'static>, u64)> = Vec::new();
+        for slot in sites.slots.iter() {
+            let site = slot.site.load(Ordering::Acquire);
+            if site.is_null() {
+                continue;
+            }
+            // SAFETY: only `&'static Location` addresses are ever stored in a slot.
+            let site: &'static Location<'static> = unsafe { &*site };
+            let count = slot.count.load(Ordering::Relaxed);
+            // The same source location is not guaranteed a single address, so merge by value.
+            match stats.iter_mut().find(|(other, _)| *other == site) {
+                Some((_, total)) => *total += count,
+                None => stats.push((site, count)),
+            }
+        }
+        stats.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_sites_are_counted_separately() {
+        let lock = RwLock::builder(0u8).site_stats_capacity(8).build().unwrap();
+        for _ in 0..3 {
+            lock.read().unwrap().release();
+        }
+        lock.safe_write(|_| ()).unwrap();
+
+        let stats = lock.site_stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats.iter().map(|(_, count)| count).collect::<Vec<_>>(),
+            [&3, &1]
+        );
+        assert!(stats.iter().all(|(site, _)| site.file() == file!()));
+        assert!(stats[0].0.line() < stats[1].0.line());
+
+        // Sites beyond the capacity are dropped, the table never grows.
+        let small = RwLock::builder(0u8).site_stats_capacity(1).build().unwrap();
+        small.read().unwrap().release();
+        small.write().unwrap().release();
+        assert_eq!(small.site_stats().len(), 1);
+        assert!(RwLock::new(0u8).site_stats().is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+//! Stale snapshots of the protected value, enabled with
+//! [`RwLockBuilder::stale_snapshots`](super::RwLockBuilder::stale_snapshots).
+//!
+//! The authoritative value stays behind the lock. Every write release publishes a clone of it
+//! into a side slot, which [`RwLock::read_stale`] reads without ever touching the lock itself.
+//! The slot also records when it was published, so [`RwLock::read_fresh_within`] can bound how
+//! stale a lock-free read may be.
+
+use super::RwLock;
+use std::{
+    fmt,
+    ops::Deref,
+    panic::{RefUnwindSafe, UnwindSafe},
+    sync::{Arc, Mutex as Mutex_, PoisonError, RwLockReadGuard},
+    time::{Duration, Instant},
+};
+
+// Type-erased snapshot slot. Only `StaleSnapshot<T>` implements it, for `T: Send + Sync`, which
+// keeps the auto traits of `RwLock<T>` exactly what they would be without the slot.
+pub(crate) trait Publish<T: ?Sized>: Send + Sync + UnwindSafe + RefUnwindSafe {
+    fn publish(&self, value: &T);
+
+    // Returns the last published value with when it was published.
+    fn load(&self) -> (Arc<T>, Instant);
+}
+
+// Builds the snapshot slot of a lock from its initial value.
+pub(crate) type NewSnapshot<T> = fn(&T) -> Box<dyn Publish<T>>;
+
+impl<T: ?Sized> fmt::Debug for dyn Publish<T> + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StaleSnapshot")
+    }
+}
+
+pub(crate) struct StaleSnapshot<T> {
+    // Only held for the time of an `Arc` clone or store, never while the value is cloned.
+    current: Mutex_<(Arc<T>, Instant)>,
+}
+
+impl<T: Clone + Send + Sync> StaleSnapshot<T> {
+    pub(crate) fn new(value: &T) -> Self {
+        StaleSnapshot {
+            current: Mutex_::new((Arc::new(value.clone()), Instant::now())),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync> Publish<T> for StaleSnapshot<T> {
+    fn publish(&self, value: &T) {
+        let next = (Arc::new(value.clone()), Instant::now());
+        let previous = std::mem::replace(
+            &mut *self.current.lock().unwrap_or_else(PoisonError::into_inner),
+            next,
+        );
+        drop(previous);
+    }
+
+    fn load(&self) -> (Arc<T>, Instant) {
+        self.current
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// A value read by [`RwLock::read_fresh_within`], either a recent enough stale snapshot or a
+/// fresh value read under the lock.
+#[derive(Debug)]
+pub enum ReadAccess<T: ?Sized> {
+    /// The stale snapshot, read without acquiring the lock.
+    Snapshot(Arc<T>),
+    /// The value read under a shared lock, which also refreshed the snapshot.
+    Fresh(Arc<T>),
+}
+
+impl<T: ?Sized> ReadAccess<T> {
+    /// Returns whether the value was read under the lock.
+    pub fn is_fresh(&self) -> bool {
+        matches!(self, Self::Fresh(_))
+    }
+
+    /// Returns the value, whichever way it was read.
+    pub fn into_arc(self) -> Arc<T> {
+        match self {
+            Self::Snapshot(value) | Self::Fresh(value) => value,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for ReadAccess<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Self::Snapshot(value) | Self::Fresh(value) => value,
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Returns the value as of the last write release, without acquiring the lock.
+    ///
+    /// Never blocks behind a writer holding the lock, at the cost of possibly returning a value
+    /// that is being replaced. Meant for observability readers that must stay off the critical
+    /// path. Writes made through [`RwLock::get_mut`] or [`RwLock::raw_write`] are only reflected
+    /// after the next regular write.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock was not built with
+    /// [`RwLockBuilder::stale_snapshots`](super::RwLockBuilder::stale_snapshots).
+    pub fn read_stale(&self) -> Arc<T> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.load().0,
+            None => panic!("{} was built without stale snapshots", self.describe()),
+        }
+    }
+
+    /// Returns the stale snapshot if it was published less than `max_age` ago, or else reads the
+    /// value under a shared lock.
+    ///
+    /// Bridges [`RwLock::read_stale`] and [`RwLock::safe_read`]: the lock is only acquired when
+    /// the snapshot is too old, in which case the snapshot is published again from the value
+    /// read, so that the following readers are served lock-free. The snapshot age is the time
+    /// since the last write release or refresh, a snapshot may be older while still current.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lock was not built with
+    /// [`RwLockBuilder::stale_snapshots`](super::RwLockBuilder::stale_snapshots).
+    #[track_caller]
+    pub fn read_fresh_within(
+        &self,
+        max_age: Duration,
+    ) -> Result<ReadAccess<T>, PoisonError<RwLockReadGuard<'_, T>>> {
+        let Some(snapshot) = &self.snapshot else {
+            panic!("{} was built without stale snapshots", self.describe());
+        };
+        let (value, published) = snapshot.load();
+        if published.elapsed() < max_age {
+            return Ok(ReadAccess::Snapshot(value));
+        }
+        self.safe_read(|value| {
+            snapshot.publish(value);
+            ReadAccess::Fresh(snapshot.load().0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn test_read_stale_does_not_block_behind_writer() {
+        let lock = RwLock::builder(vec![1u32])
+            .stale_snapshots()
+            .build()
+            .unwrap();
+        lock.super_safe_write(|v| v.push(2));
+        assert_eq!(*lock.read_stale(), vec![1, 2]);
+
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|s| {
+            let mut writer = lock.write().unwrap();
+            writer.push(3);
+            s.spawn(|| tx.send(lock.read_stale()).unwrap());
+            // The writer still holds the lock: the snapshot is served anyway, without the push.
+            let stale = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(*stale, vec![1, 2]);
+            writer.release();
+        });
+        assert_eq!(*lock.read_stale(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_panicking_writes_are_not_published() {
+        let lock = RwLock::builder(vec![1u32])
+            .stale_snapshots()
+            .build()
+            .unwrap();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            lock.safe_write(|v| {
+                v.push(2);
+                panic!("half written");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(*lock.read_stale(), vec![1]);
+        assert_eq!(lock.version(), 0);
+        lock.unpoison();
+        // A strict guard dropped while unwinding is not published either.
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let mut writer = lock.write().unwrap();
+            writer.push(3);
+            panic!("half written");
+        }));
+        assert!(result.is_err());
+        assert!(lock.is_poisoned());
+        assert_eq!(*lock.read_stale(), vec![1]);
+        // Only the unpoison counted as a write.
+        assert_eq!(lock.version(), 1);
+    }
+
+    #[test]
+    fn test_read_fresh_within_only_locks_for_old_snapshots() {
+        let lock = RwLock::builder(1u32).stale_snapshots().build().unwrap();
+        lock.super_safe_write(|v| *v = 2);
+        let writer = lock.write().unwrap();
+        // A recent snapshot is served although the writer holds the lock.
+        let recent = lock.read_fresh_within(Duration::from_secs(60)).unwrap();
+        assert!(!recent.is_fresh());
+        assert_eq!(*recent, 2);
+        writer.release();
+
+        thread::sleep(Duration::from_millis(20));
+        let fresh = lock.read_fresh_within(Duration::from_millis(10)).unwrap();
+        assert!(fresh.is_fresh());
+        assert_eq!(*fresh.into_arc(), 2);
+        // The refreshed snapshot serves the next readers.
+        let next = lock.read_fresh_within(Duration::from_secs(60)).unwrap();
+        assert!(!next.is_fresh());
+    }
+}
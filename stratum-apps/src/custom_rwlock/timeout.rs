@@ -0,0 +1,601 @@
+//! Acquisition with a timeout.
+//!
+//! [`std::sync::RwLock`] has no timed acquisition, so these poll a non-blocking attempt with the
+//! [`Backoff`] of the lock until the lock is acquired or the timeout elapses.
+//!
+//! There is no recursive variant. The std lock may refuse a shared lock to a thread that already
+//! holds one while a writer is waiting, and handing out the value without acquiring the lock
+//! would be unsound, since the outer guard could be released while the value is still borrowed.
+//! A reentrant read through [`RwLock::read_timeout`] is bounded: at worst it times out instead
+//! of deadlocking.
+//!
+//! Locks built with [`RwLockBuilder::timeout_stats`](super::RwLockBuilder::timeout_stats) keep
+//! probing for a bounded time after a timed acquisition gave up, to record by how much it missed
+//! the lock, see [`RwLock::timeout_stats`].
+//!
+//! A [`Budget`] shares a single deadline across the acquisitions of several locks, e.g. to hold
+//! a request handler to its end-to-end latency target.
+
+use super::{Backoff, ReadGuard, RwLock, WriteGuard};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        PoisonError, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Error returned by timed and validated acquisitions, generic over the std guard of the poisoned
+/// case.
+pub enum LockError<G> {
+    /// The lock could not be acquired before the timeout elapsed.
+    TimedOut,
+    /// The lock is poisoned. The std guard can be recovered with [`PoisonError::into_inner`].
+    Poisoned(PoisonError<G>),
+    /// The value failed the validation of [`RwLock::read_validated`]. The lock was released.
+    Invalid,
+}
+
+impl<G> fmt::Debug for LockError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TimedOut => write!(f, "TimedOut"),
+            Self::Poisoned(_) => write!(f, "Poisoned(..)"),
+            Self::Invalid => write!(f, "Invalid"),
+        }
+    }
+}
+
+impl<G> fmt::Display for LockError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TimedOut => write!(f, "Timed out waiting for the lock"),
+            Self::Poisoned(_) => write!(f, "Lock poisoned"),
+            Self::Invalid => write!(f, "Locked value failed validation"),
+        }
+    }
+}
+
+impl<G> std::error::Error for LockError<G> {}
+
+impl<G> From<PoisonError<G>> for LockError<G> {
+    fn from(e: PoisonError<G>) -> Self {
+        LockError::Poisoned(e)
+    }
+}
+
+/// Overall time budget shared by a sequence of acquisitions, see [`RwLock::read_budgeted`].
+///
+/// Every acquisition waits at most for what is left of the budget, so the whole sequence gives
+/// up once the deadline has passed, however the time was spent between the acquisitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    // `None` for a budget too large to hold a deadline, which is never exhausted.
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Creates a budget of `total`, starting now. A budget too large to hold a deadline, such as
+    /// [`Duration::MAX`], is never exhausted.
+    pub fn new(total: Duration) -> Self {
+        Budget {
+            deadline: Instant::now().checked_add(total),
+        }
+    }
+
+    /// Creates a budget ending at `deadline`.
+    pub fn until(deadline: Instant) -> Self {
+        Budget {
+            deadline: Some(deadline),
+        }
+    }
+
+    /// Time left before the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.map_or(Duration::MAX, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        })
+    }
+
+    /// Returns whether the deadline has passed.
+    pub fn is_exhausted(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Timed acquisitions that gave up on a lock, see [`RwLock::timeout_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeoutStats {
+    /// Timed acquisitions that timed out.
+    pub timeouts: u64,
+    /// Timeouts after which the lock stayed unavailable for the whole probe duration, left out
+    /// of the overruns since their actual overrun is unknown.
+    pub unresolved: u64,
+    /// Longest time between a resolved timeout and the lock becoming available.
+    pub max_overrun: Duration,
+    /// Sum of the time between every resolved timeout and the lock becoming available, to be
+    /// averaged over `timeouts - unresolved`.
+    pub total_overrun: Duration,
+}
+
+#[derive(Debug)]
+pub(crate) struct TimeoutCounters {
+    probe: Duration,
+    timeouts: AtomicU64,
+    unresolved: AtomicU64,
+    max_overrun_nanos: AtomicU64,
+    total_overrun_nanos: AtomicU64,
+}
+
+impl TimeoutCounters {
+    pub(crate) fn new(probe: Duration) -> Self {
+        TimeoutCounters {
+            probe,
+            timeouts: AtomicU64::new(0),
+            unresolved: AtomicU64::new(0),
+            max_overrun_nanos: AtomicU64::new(0),
+            total_overrun_nanos: AtomicU64::new(0),
+        }
+    }
+
+    // Keeps probing with `attempt` after a timeout, for at most the probe duration, and records
+    // how long the lock stayed unavailable, if it became available. A poisoned lock counts as
+    // available.
+    fn record<G>(
+        &self,
+        backoff: &dyn Backoff,
+        attempt: impl FnMut() -> Result<G, TryLockError<G>>,
+    ) {
+        let began = Instant::now();
+        let resolved = !matches!(poll(self.probe, backoff, attempt), Err(LockError::TimedOut));
+        let overrun = u64::try_from(began.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+        if !resolved {
+            self.unresolved.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.max_overrun_nanos.fetch_max(overrun, Ordering::Relaxed);
+        self.total_overrun_nanos
+            .fetch_add(overrun, Ordering::Relaxed);
+    }
+}
+
+// Retries `attempt` until it stops returning `WouldBlock` or `timeout` elapses. A timeout too
+// large to be represented as a deadline, such as `Duration::MAX`, never elapses.
+pub(crate) fn poll<G>(
+    timeout: Duration,
+    backoff: &dyn Backoff,
+    mut attempt: impl FnMut() -> Result<G, TryLockError<G>>,
+) -> Result<G, LockError<G>> {
+    let deadline = Instant::now().checked_add(timeout);
+    let mut failed = 1;
+    loop {
+        match attempt() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(e)) => return Err(LockError::Poisoned(e)),
+            Err(TryLockError::WouldBlock) => {}
+        }
+        let mut delay = backoff.delay(failed);
+        if let Some(deadline) = deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(LockError::TimedOut);
+            }
+            delay = delay.min(deadline - now);
+        }
+        thread::sleep(delay);
+        failed += 1;
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquires a shared lock, giving up after `timeout`.
+    ///
+    /// A timeout too large to hold a deadline, such as [`Duration::MAX`], never gives up.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn read_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<ReadGuard<'_, T>, LockError<RwLockReadGuard<'_, T>>> {
+        self.read_within(timeout, true)
+    }
+
+    /// Acquires an exclusive lock, giving up after `timeout`.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn write_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<WriteGuard<'_, T>, LockError<RwLockWriteGuard<'_, T>>> {
+        self.write_within(timeout, true)
+    }
+
+    /// Acquires a shared lock, giving up once `budget` is exhausted.
+    ///
+    /// Same as [`RwLock::read_timeout`] with what is left of the budget as timeout, except that
+    /// an exhausted budget fails right away, without trying to acquire the lock. Budgeted
+    /// timeouts are left out of [`RwLock::timeout_stats`]: probing the lock after the timeout
+    /// would overrun the budget.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn read_budgeted(
+        &self,
+        budget: &Budget,
+    ) -> Result<ReadGuard<'_, T>, LockError<RwLockReadGuard<'_, T>>> {
+        if budget.is_exhausted() {
+            return Err(LockError::TimedOut);
+        }
+        self.read_within(budget.remaining(), false)
+    }
+
+    /// Acquires an exclusive lock, giving up once `budget` is exhausted, see
+    /// [`RwLock::read_budgeted`].
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn write_budgeted(
+        &self,
+        budget: &Budget,
+    ) -> Result<WriteGuard<'_, T>, LockError<RwLockWriteGuard<'_, T>>> {
+        if budget.is_exhausted() {
+            return Err(LockError::TimedOut);
+        }
+        self.write_within(budget.remaining(), false)
+    }
+
+    // Timed shared acquisition, which only records a timeout in the timeout stats if `record`.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    fn read_within(
+        &self,
+        timeout: Duration,
+        record: bool,
+    ) -> Result<ReadGuard<'_, T>, LockError<RwLockReadGuard<'_, T>>> {
+        let guard = poll(timeout, &self.backoff(), || self.inner.try_read()).inspect_err(|e| {
+            if let (LockError::TimedOut, Some(stats), true) = (e, &self.timeouts, record) {
+                stats.record(&self.backoff(), || self.inner.try_read());
+            }
+        })?;
+        Ok(ReadGuard::new(self, guard))
+    }
+
+    // Timed exclusive acquisition, see `read_within`.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    fn write_within(
+        &self,
+        timeout: Duration,
+        record: bool,
+    ) -> Result<WriteGuard<'_, T>, LockError<RwLockWriteGuard<'_, T>>> {
+        self.debug_assert_writable();
+        let guard = poll(timeout, &self.backoff(), || self.inner.try_write()).inspect_err(|e| {
+            if let (LockError::TimedOut, Some(stats), true) = (e, &self.timeouts, record) {
+                stats.record(&self.backoff(), || self.inner.try_write());
+            }
+        })?;
+        Ok(WriteGuard::new(self, guard))
+    }
+
+    /// Returns the timeouts of [`RwLock::read_timeout`] and [`RwLock::write_timeout`] so far.
+    ///
+    /// All zero unless the lock was built with
+    /// [`RwLockBuilder::timeout_stats`](super::RwLockBuilder::timeout_stats). A large overrun
+    /// compared to the timeouts in use means they are too tight for how long the lock is held.
+    /// Counters are relaxed, so concurrent timeouts may or may not be included.
+    pub fn timeout_stats(&self) -> TimeoutStats {
+        let Some(stats) = &self.timeouts else {
+            return TimeoutStats::default();
+        };
+        TimeoutStats {
+            timeouts: stats.timeouts.load(Ordering::Relaxed),
+            unresolved: stats.unresolved.load(Ordering::Relaxed),
+            max_overrun: Duration::from_nanos(stats.max_overrun_nanos.load(Ordering::Relaxed)),
+            total_overrun: Duration::from_nanos(stats.total_overrun_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Waits until the lock is free, no reader nor writer holding it, or `timeout` elapses.
+    ///
+    /// Returns whether the lock was drained. The exclusive lock is only taken for the instant of
+    /// the successful probe and is released on return, so nothing prevents new readers from
+    /// coming in right after: this is meant for quiescence checks before a reconfiguration, not
+    /// as a substitute for holding the lock. A poisoned lock counts as drained.
+    pub fn drain_readers(&self, timeout: Duration) -> bool {
+        match poll(timeout, &self.backoff(), || self.inner.try_write()) {
+            Ok(_) | Err(LockError::Poisoned(_)) => true,
+            Err(_) => false,
+        }
+    }
+
+    /// Waits for the lock to drain, up to `drain_timeout`, then runs `f` under the exclusive lock.
+    ///
+    /// Unlike [`RwLock::drain_readers`] followed by [`RwLock::write`], the exclusive lock is kept
+    /// from the probe that found the lock free, so `f` runs without any reader having been
+    /// blocked. If the lock does not drain in time, `f` still runs once the exclusive lock is
+    /// acquired, see [`RwLock::reconfigure_if_drained`] to give up instead.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn reconfigure<F>(
+        &self,
+        drain_timeout: Duration,
+        f: F,
+    ) -> Result<(), LockError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnOnce(&mut T),
+    {
+        self.reconfigure_with(drain_timeout, false, f)
+    }
+
+    /// Same as [`RwLock::reconfigure`], except that it returns [`LockError::TimedOut`] without
+    /// running `f` if the lock does not drain within `drain_timeout`.
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    pub fn reconfigure_if_drained<F>(
+        &self,
+        drain_timeout: Duration,
+        f: F,
+    ) -> Result<(), LockError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnOnce(&mut T),
+    {
+        self.reconfigure_with(drain_timeout, true, f)
+    }
+
+    #[allow(clippy::type_complexity)]
+    #[track_caller]
+    fn reconfigure_with<F>(
+        &self,
+        drain_timeout: Duration,
+        abort: bool,
+        f: F,
+    ) -> Result<(), LockError<RwLockWriteGuard<'_, T>>>
+    where
+        F: FnOnce(&mut T),
+    {
+        self.debug_assert_writable();
+        let mut guard = match poll(drain_timeout, &self.backoff(), || self.inner.try_write()) {
+            Ok(guard) => WriteGuard::new(self, guard),
+            Err(LockError::TimedOut) if !abort => self.write()?,
+            Err(e) => return Err(e),
+        };
+        f(&mut guard);
+        guard.release();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{atomic::AtomicBool, Barrier},
+    };
+
+    #[test]
+    fn test_timeout_and_poison_are_distinct() {
+        let lock = RwLock::new(0u8);
+        let writer = lock.write().unwrap();
+        let result = lock.read_timeout(Duration::from_millis(10));
+        assert!(matches!(result, Err(LockError::TimedOut)));
+        writer.release();
+        lock.read_timeout(Duration::from_millis(10))
+            .unwrap()
+            .release();
+
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            lock.super_safe_write(|_| panic!("poison the lock"));
+        }));
+        match lock.write_timeout(Duration::from_millis(10)) {
+            Err(LockError::Poisoned(e)) => assert_eq!(*e.into_inner(), 0),
+            Err(e) => panic!("expected a poisoned lock, got {e:?}"),
+            Ok(guard) => {
+                guard.release();
+                panic!("expected a poisoned lock, got the guard");
+            }
+        };
+    }
+
+    #[test]
+    fn test_unbounded_timeouts_wait_for_the_lock() {
+        let lock = RwLock::new(0u8);
+        lock.read_timeout(Duration::MAX).unwrap().release();
+        lock.write_timeout(Duration::MAX).unwrap().release();
+        assert!(lock.drain_readers(Duration::MAX));
+        lock.reconfigure_if_drained(Duration::MAX, |v| *v += 1)
+            .unwrap();
+
+        let held = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let writer = lock.write().unwrap();
+                held.wait();
+                thread::sleep(Duration::from_millis(20));
+                writer.release();
+            });
+            held.wait();
+            let reader = lock.read_timeout(Duration::MAX).unwrap();
+            assert_eq!(*reader, 1);
+            reader.release();
+        });
+    }
+
+    #[test]
+    fn test_drain_readers_waits_for_the_last_reader() {
+        let lock = RwLock::new(0u8);
+        let held = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let reader = lock.read().unwrap();
+                held.wait();
+                thread::sleep(Duration::from_millis(50));
+                reader.release();
+            });
+            held.wait();
+            assert!(!lock.drain_readers(Duration::from_millis(10)));
+            assert!(lock.drain_readers(Duration::from_secs(5)));
+        });
+        // The probe did not keep the lock nor count as a write.
+        lock.read().unwrap().release();
+        assert_eq!(lock.version(), 0);
+    }
+
+    #[test]
+    fn test_timeout_stats_measure_the_overrun() {
+        let lock = RwLock::builder(0u8)
+            .timeout_stats(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let writer = lock.write().unwrap();
+        for _ in 0..3 {
+            assert!(matches!(
+                lock.read_timeout(Duration::from_millis(5)),
+                Err(LockError::TimedOut)
+            ));
+        }
+        writer.release();
+        let stats = lock.timeout_stats();
+        assert_eq!((stats.timeouts, stats.unresolved), (3, 3));
+        // The lock never became available during the probes: no overrun is known.
+        assert_eq!(
+            (stats.max_overrun, stats.total_overrun),
+            (Duration::ZERO, Duration::ZERO)
+        );
+
+        // A lock released before the end of the probe records the actual overrun.
+        let lock = RwLock::builder(0u8)
+            .timeout_stats(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let held = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let writer = lock.write().unwrap();
+                held.wait();
+                thread::sleep(Duration::from_millis(50));
+                writer.release();
+            });
+            held.wait();
+            assert!(lock.write_timeout(Duration::from_millis(10)).is_err());
+        });
+        let stats = lock.timeout_stats();
+        assert_eq!((stats.timeouts, stats.unresolved), (1, 0));
+        assert!(stats.max_overrun >= Duration::from_millis(30));
+        assert!(stats.max_overrun < Duration::from_secs(5));
+        assert_eq!(RwLock::new(0u8).timeout_stats(), TimeoutStats::default());
+    }
+
+    #[test]
+    fn test_reconfigure_per_drain_policy() {
+        let lock = RwLock::new(0u32);
+        lock.reconfigure(Duration::from_millis(10), |v| *v = 1)
+            .unwrap();
+        assert_eq!(lock.version(), 1);
+
+        // A reader outliving the drain timeout: abort, or apply once it is gone.
+        let held = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let reader = lock.read().unwrap();
+                held.wait();
+                held.wait();
+                thread::sleep(Duration::from_millis(50));
+                reader.release();
+            });
+            held.wait();
+            let result = lock.reconfigure_if_drained(Duration::from_millis(10), |v| *v = 2);
+            assert!(matches!(result, Err(LockError::TimedOut)));
+            held.wait();
+            let started = Instant::now();
+            lock.reconfigure(Duration::from_millis(10), |v| *v = 3)
+                .unwrap();
+            assert!(started.elapsed() >= Duration::from_millis(30));
+        });
+        assert_eq!(lock.super_safe_read(|v| *v), 3);
+
+        // Under a steady stream of readers, the change lands either way.
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        lock.super_safe_read(|_| thread::sleep(Duration::from_micros(200)));
+                    }
+                });
+            }
+            lock.reconfigure(Duration::from_millis(5), |v| *v = 4)
+                .unwrap();
+            done.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(lock.super_safe_read(|v| *v), 4);
+    }
+
+    #[test]
+    fn test_budget_is_shared_across_locks() {
+        let (jobs, shares, totals) = (RwLock::new(0u8), RwLock::new(0u8), RwLock::new(0u8));
+        let budget = Budget::new(Duration::from_millis(40));
+        let held = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let writer = shares.write().unwrap();
+                held.wait();
+                thread::sleep(Duration::from_millis(200));
+                writer.release();
+            });
+            held.wait();
+            jobs.write_budgeted(&budget).unwrap().release();
+            // Waiting for `shares` spends the rest of the budget.
+            let started = Instant::now();
+            assert!(matches!(
+                shares.read_budgeted(&budget),
+                Err(LockError::TimedOut)
+            ));
+            assert!(started.elapsed() < Duration::from_millis(150));
+            assert!(budget.is_exhausted());
+            assert_eq!(budget.remaining(), Duration::ZERO);
+            // Even a free lock is refused once the budget is spent.
+            assert!(matches!(
+                totals.write_budgeted(&budget),
+                Err(LockError::TimedOut)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_budget_bounds_the_timeout_probes() {
+        let probe = Duration::from_millis(300);
+        let locks = [0u8, 1].map(|v| RwLock::builder(v).timeout_stats(probe).build().unwrap());
+        let budget = Budget::new(Duration::from_millis(30));
+        let held = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let writers = locks.each_ref().map(|lock| lock.write().unwrap());
+                held.wait();
+                held.wait();
+                writers.into_iter().for_each(WriteGuard::release);
+            });
+            held.wait();
+            let started = Instant::now();
+            assert!(locks[0].read_budgeted(&budget).is_err());
+            assert!(locks[1].write_budgeted(&budget).is_err());
+            // The budgeted timeout did not probe the lock past the budget.
+            assert!(started.elapsed() < Duration::from_millis(30) + Duration::from_millis(100));
+            held.wait();
+        });
+        // Nor was it recorded, while a plain timeout still is.
+        assert_eq!(locks[0].timeout_stats(), TimeoutStats::default());
+        let writer = locks[0].write().unwrap();
+        assert!(locks[0].read_timeout(Duration::from_millis(1)).is_err());
+        writer.release();
+        assert_eq!(locks[0].timeout_stats().timeouts, 1);
+
+        // A budget too large to hold a deadline is never exhausted.
+        let unbounded = Budget::new(Duration::MAX);
+        assert!(!unbounded.is_exhausted());
+        assert_eq!(unbounded.remaining(), Duration::MAX);
+        locks[1].write_budgeted(&unbounded).unwrap().release();
+    }
+}
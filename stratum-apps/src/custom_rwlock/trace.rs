@@ -0,0 +1,100 @@
+//! Bounded trace of the most recent acquisitions of a [`RwLock`], enabled with
+//! [`RwLockBuilder::trace_capacity`](super::RwLockBuilder::trace_capacity).
+
+use super::{holders::HeldMode, RwLock};
+use std::{
+    collections::VecDeque,
+    panic::Location,
+    sync::{Mutex as Mutex_, PoisonError},
+    time::Instant,
+};
+
+/// One acquisition recorded in the trace of a [`RwLock`].
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEntry {
+    /// Where the lock was acquired.
+    pub site: &'static Location<'static>,
+    /// Whether the lock was acquired for writing.
+    pub exclusive: bool,
+    /// When the lock was acquired.
+    pub at: Instant,
+}
+
+// Ring buffer allocated once at construction: recording never reallocates.
+#[derive(Debug)]
+pub(crate) struct AcquisitionTrace {
+    capacity: usize,
+    entries: Mutex_<VecDeque<TraceEntry>>,
+}
+
+impl AcquisitionTrace {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        AcquisitionTrace {
+            capacity,
+            entries: Mutex_::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub(crate) fn record(&self, site: &'static Location<'static>, mode: HeldMode) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(TraceEntry {
+            site,
+            exclusive: mode == HeldMode::Write,
+            at: Instant::now(),
+        });
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Returns the most recent acquisitions of this lock, oldest first.
+    ///
+    /// Empty unless the lock was built with a trace capacity, in which case at most that many
+    /// acquisitions are kept.
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        match &self.trace {
+            Some(trace) => trace
+                .entries
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_keeps_latest_without_reallocating() {
+        let lock = RwLock::builder(0u8).trace_capacity(4).build().unwrap();
+        let trace = lock.trace.as_ref().unwrap();
+        let capacity = trace.entries.lock().unwrap().capacity();
+        assert!(capacity >= 4);
+
+        for _ in 0..20 {
+            lock.read().unwrap().release();
+        }
+        lock.write().unwrap().release();
+
+        assert_eq!(trace.entries.lock().unwrap().capacity(), capacity);
+        let entries = lock.trace();
+        assert_eq!(entries.len(), 4);
+        assert!(entries[..3].iter().all(|entry| !entry.exclusive));
+        assert!(entries[3].exclusive);
+        assert_eq!(entries[3].site.file(), file!());
+    }
+
+    #[test]
+    fn test_untraced_lock_records_nothing() {
+        let lock = RwLock::new(0u8);
+        lock.read().unwrap().release();
+        assert!(lock.trace().is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+//! Read, decide, maybe write: reads that only take the exclusive lock when they need it.
+//!
+//! [`std::sync::RwLock`] has no upgradable reads, so [`RwLock::upgradable_read_then`] gets the
+//! same guarantee from the version counter: the decision is taken under a shared lock, and if it
+//! calls for a write, the write only goes ahead if no other write happened in between. Otherwise
+//! the decision is taken again, against the newer value, under the exclusive lock.
+
+use super::RwLock;
+use std::sync::PoisonError;
+
+/// Decision taken by the check of [`RwLock::upgradable_read_then`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upgrade<R> {
+    /// The value needs to be written.
+    Yes,
+    /// The value needs no write, the read alone produced the result.
+    No(R),
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Runs `check` under a shared lock and, if it returns [`Upgrade::Yes`], runs `on_upgrade`
+    /// under the exclusive lock.
+    ///
+    /// `on_upgrade` always runs against the value `check` approved: no write can happen between
+    /// the two. When one did, `check` runs again under the exclusive lock before `on_upgrade`,
+    /// which is why it may be called twice. Readers are only kept out when a write is needed.
+    /// Writes made through [`RwLock::raw_write`] are not seen as intervening writes.
+    #[track_caller]
+    pub fn upgradable_read_then<F, G, R>(
+        &self,
+        mut check: F,
+        on_upgrade: G,
+    ) -> Result<R, PoisonError<()>>
+    where
+        F: FnMut(&T) -> Upgrade<R>,
+        G: FnOnce(&mut T) -> R,
+    {
+        let (version, decision) = self
+            .safe_read(|value| (self.version(), check(value)))
+            .map_err(|_| PoisonError::new(()))?;
+        if let Upgrade::No(result) = decision {
+            return Ok(result);
+        }
+        self.safe_write(|value| {
+            // Writers bump the version under the exclusive lock, so an unchanged version means
+            // the value is still the one `check` approved.
+            if self.version() != version {
+                if let Upgrade::No(result) = check(value) {
+                    return result;
+                }
+            }
+            on_upgrade(value)
+        })
+        .map_err(|_| PoisonError::new(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_both_outcomes() {
+        let lock = RwLock::new(Vec::<u32>::new());
+        let first = lock.upgradable_read_then(
+            |values| match values.first() {
+                Some(first) => Upgrade::No(*first),
+                None => Upgrade::Yes,
+            },
+            |values| {
+                values.push(7);
+                7
+            },
+        );
+        assert_eq!(first.unwrap(), 7);
+        let first = lock.upgradable_read_then(
+            |values| Upgrade::No(values[0]),
+            |_| unreachable!("the value is already initialized"),
+        );
+        assert_eq!(first.unwrap(), 7);
+        assert_eq!(lock.version(), 1);
+    }
+
+    #[test]
+    fn test_no_write_between_check_and_upgrade() {
+        const LIMIT: u32 = 1000;
+        let lock = RwLock::new(0u32);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| loop {
+                    let incremented = lock
+                        .upgradable_read_then(
+                            |v| {
+                                if *v < LIMIT {
+                                    Upgrade::Yes
+                                } else {
+                                    Upgrade::No(false)
+                                }
+                            },
+                            |v| {
+                                *v += 1;
+                                true
+                            },
+                        )
+                        .unwrap();
+                    if !incremented {
+                        break;
+                    }
+                });
+            }
+        });
+        // A stale decision would have let some threads increment past the limit.
+        assert_eq!(lock.into_inner(), LIMIT);
+    }
+}
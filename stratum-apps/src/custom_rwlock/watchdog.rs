@@ -0,0 +1,294 @@
+//! Last-resort liveness check reporting guards held past a deadlock timeout, see
+//! [`RwLock::with_deadlock_timeout`].
+//!
+//! Unlike the hold time warning, which is logged on release, the watchdog reports a guard while
+//! it is still held, so a hung holder is noticed even though it never releases. Every watched
+//! acquisition registers its deadline in a process wide registry, served by a single background
+//! thread spawned on the first watched acquisition, which sleeps until the earliest deadline.
+//! Locks built without a deadlock timeout never touch the registry.
+
+use super::{BuildError, RwLock};
+use std::{
+    collections::HashMap,
+    fmt,
+    panic::{Location, RefUnwindSafe, UnwindSafe},
+    sync::{Arc, Condvar, Mutex as Mutex_, MutexGuard, Once, OnceLock, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A guard held for longer than the deadlock timeout of its lock.
+#[derive(Debug, Clone)]
+pub struct Deadlock {
+    lock: String,
+    site: &'static Location<'static>,
+    held_for: Duration,
+}
+
+impl Deadlock {
+    /// Identifier of the lock, its label if it has one.
+    pub fn lock(&self) -> &str {
+        &self.lock
+    }
+
+    /// Where the guard was acquired.
+    pub fn site(&self) -> &'static Location<'static> {
+        self.site
+    }
+
+    /// How long the guard had been held when the watchdog noticed.
+    pub fn held_for(&self) -> Duration {
+        self.held_for
+    }
+}
+
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} held for {:?} by the guard acquired at {}",
+            self.lock, self.held_for, self.site
+        )
+    }
+}
+
+/// Called by the watchdog with every guard held past its deadline.
+///
+/// Unwind safe like the rest of the lock, so that locks stay usable across `catch_unwind`.
+#[derive(Clone)]
+pub(crate) struct OnDeadlock(
+    pub(crate) Arc<dyn Fn(&Deadlock) + Send + Sync + UnwindSafe + RefUnwindSafe>,
+);
+
+impl fmt::Debug for OnDeadlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OnDeadlock(..)")
+    }
+}
+
+/// Deadlock timeout of a lock, see [`RwLock::with_deadlock_timeout`].
+#[derive(Debug)]
+pub(crate) struct DeadlockWatch {
+    timeout: Duration,
+    on_deadlock: Option<OnDeadlock>,
+}
+
+impl DeadlockWatch {
+    pub(crate) fn new(timeout: Duration, on_deadlock: Option<OnDeadlock>) -> Self {
+        DeadlockWatch {
+            timeout,
+            on_deadlock,
+        }
+    }
+
+    // Registers an acquisition from `site` until the returned token is dropped. Nothing is
+    // registered if the timeout is too large to ever be reached, which also spares describing
+    // the lock.
+    pub(crate) fn watch(
+        &self,
+        lock: impl FnOnce() -> String,
+        site: &'static Location<'static>,
+    ) -> Option<Watched> {
+        let started = Instant::now();
+        let deadline = started.checked_add(self.timeout)?;
+        let lock = lock();
+        let registry = registry();
+        let mut state = registry.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.entries.insert(
+            id,
+            Entry {
+                lock,
+                site,
+                started,
+                deadline,
+                on_deadlock: self.on_deadlock.clone(),
+                reported: false,
+            },
+        );
+        drop(state);
+        registry.changed.notify_one();
+        Some(Watched(id))
+    }
+}
+
+/// Registration of one watched acquisition, removed when dropped.
+pub(crate) struct Watched(u64);
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        registry().lock().entries.remove(&self.0);
+    }
+}
+
+struct Entry {
+    lock: String,
+    site: &'static Location<'static>,
+    started: Instant,
+    deadline: Instant,
+    on_deadlock: Option<OnDeadlock>,
+    // Set once reported, so a hung holder is only reported once.
+    reported: bool,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    entries: HashMap<u64, Entry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    state: Mutex_<State>,
+    changed: Condvar,
+}
+
+// Returns the registry, spawning the watchdog thread on first use.
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    static WATCHDOG: Once = Once::new();
+    let registry = REGISTRY.get_or_init(Registry::default);
+    WATCHDOG.call_once(|| {
+        thread::Builder::new()
+            .name("rwlock-watchdog".into())
+            .spawn(move || registry.run())
+            .expect("failed to spawn the RwLock watchdog thread");
+    });
+    registry
+}
+
+impl Registry {
+    // Entries are only inserted and removed under this mutex, poisoning cannot leave it torn.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run(&self) {
+        let mut state = self.lock();
+        loop {
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            let mut next_deadline = None::<Instant>;
+            for entry in state.entries.values_mut().filter(|entry| !entry.reported) {
+                if entry.deadline <= now {
+                    entry.reported = true;
+                    let deadlock = Deadlock {
+                        lock: entry.lock.clone(),
+                        site: entry.site,
+                        held_for: now - entry.started,
+                    };
+                    expired.push((deadlock, entry.on_deadlock.clone()));
+                } else {
+                    next_deadline = Some(match next_deadline {
+                        Some(next) => next.min(entry.deadline),
+                        None => entry.deadline,
+                    });
+                }
+            }
+            if !expired.is_empty() {
+                // Reported without the registry locked, callbacks may acquire locks themselves.
+                drop(state);
+                for (deadlock, on_deadlock) in expired {
+                    match on_deadlock {
+                        Some(on_deadlock) => (on_deadlock.0)(&deadlock),
+                        None => tracing::error!("Possible deadlock: {deadlock}"),
+                    }
+                }
+                state = self.lock();
+                continue;
+            }
+            state = match next_deadline {
+                Some(deadline) => {
+                    self.changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Creates a lock whose guards are reported by the watchdog when held longer than `timeout`.
+    ///
+    /// A hold that long is assumed to be a deadlock or a hung holder. Reports are logged as
+    /// errors with the location the guard was acquired at, or handed to the callback set with
+    /// [`RwLockBuilder::on_deadlock`](super::RwLockBuilder::on_deadlock), which can abort the
+    /// process. Each guard is reported at most once. Watched acquisitions register with the
+    /// watchdog, which takes a process wide mutex, so this is meant for coarse grained locks.
+    /// A timeout too large to ever be reached, such as [`Duration::MAX`], watches nothing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `timeout` is zero, which would report every acquisition, as
+    /// [`RwLockBuilder::build`](super::RwLockBuilder::build) rejects with
+    /// [`BuildError::ZeroDeadlockTimeout`](super::BuildError::ZeroDeadlockTimeout).
+    #[track_caller]
+    pub fn with_deadlock_timeout(value: T, timeout: Duration) -> Self {
+        assert!(!timeout.is_zero(), "{}", BuildError::ZeroDeadlockTimeout);
+        let mut lock = RwLock::new(value);
+        lock.deadlock = Some(DeadlockWatch::new(timeout, None));
+        lock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::mpsc,
+    };
+
+    #[test]
+    fn test_only_overlong_holds_are_reported() {
+        let (reports, reported) = mpsc::channel();
+        let lock = RwLock::builder(0u8)
+            .label("jobs")
+            .deadlock_timeout(Duration::from_millis(30))
+            .on_deadlock(move |deadlock| {
+                let _ = reports.send(deadlock.clone());
+            })
+            .build()
+            .unwrap();
+
+        lock.read().unwrap().release();
+        lock.safe_write(|_| thread::sleep(Duration::from_millis(5)))
+            .unwrap();
+        assert!(reported.recv_timeout(Duration::from_millis(100)).is_err());
+
+        let guard = lock.write().unwrap();
+        let deadlock = reported.recv_timeout(Duration::from_secs(5)).unwrap();
+        guard.release();
+        assert!(deadlock.lock().starts_with("jobs "));
+        assert_eq!(deadlock.site().file(), file!());
+        assert!(deadlock.held_for() >= Duration::from_millis(30));
+        // Reported once, and released guards are forgotten.
+        assert!(reported.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_unreachable_and_zero_timeouts() {
+        let lock = RwLock::with_deadlock_timeout(0u8, Duration::MAX);
+        let guard = lock.write().unwrap();
+        let described = lock.describe();
+        let watched = registry()
+            .lock()
+            .entries
+            .values()
+            .any(|entry| entry.lock == described);
+        guard.release();
+        assert!(!watched);
+
+        let zero = catch_unwind(AssertUnwindSafe(|| {
+            RwLock::with_deadlock_timeout(0u8, Duration::ZERO)
+        }));
+        assert!(zero.is_err());
+    }
+}
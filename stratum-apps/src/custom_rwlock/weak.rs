@@ -0,0 +1,91 @@
+//! Non-owning handles to a shared [`RwLock`].
+
+use super::{AutoReadGuard, AutoWriteGuard, RwLock};
+use std::sync::{Arc, Weak};
+
+/// Non-owning handle to an `Arc<RwLock<T>>`, obtained with [`WeakRwLock::new`].
+///
+/// Like [`std::sync::Weak`], it does not keep the lock alive, which makes it a good fit for
+/// observer registries that must not prevent teardown of what they observe. On top of `upgrade`
+/// it offers closure-based access that returns `None` once the lock has been dropped.
+pub struct WeakRwLock<T>(Weak<RwLock<T>>);
+
+impl<T> WeakRwLock<T> {
+    /// Creates a non-owning handle to `lock`.
+    pub fn new(lock: &Arc<RwLock<T>>) -> Self {
+        WeakRwLock(Arc::downgrade(lock))
+    }
+
+    /// Returns the shared lock, if it is still alive.
+    pub fn upgrade(&self) -> Option<Arc<RwLock<T>>> {
+        self.0.upgrade()
+    }
+
+    /// Runs `thunk` under a shared lock, if the lock is still alive.
+    ///
+    /// Returns `None` if the lock has been dropped or is poisoned.
+    pub fn try_read<F, Ret>(&self, thunk: F) -> Option<Ret>
+    where
+        F: FnOnce(&T) -> Ret,
+    {
+        self.upgrade()?.safe_read(thunk).ok()
+    }
+
+    /// Runs `thunk` under an exclusive lock, if the lock is still alive.
+    ///
+    /// Returns `None` if the lock has been dropped or is poisoned.
+    pub fn try_write<F, Ret>(&self, thunk: F) -> Option<Ret>
+    where
+        F: FnOnce(&mut T) -> Ret,
+    {
+        self.upgrade()?.safe_write(thunk).ok()
+    }
+
+    /// Upgrades the handle and acquires a shared lock on it, handing both to `thunk`.
+    ///
+    /// Useful when the caller needs a guard rather than a plain reference. Returns `None` if the
+    /// lock has been dropped or is poisoned.
+    pub fn with_read_guard<F, Ret>(&self, thunk: F) -> Option<Ret>
+    where
+        F: FnOnce(AutoReadGuard<'_, T>) -> Ret,
+    {
+        let lock = self.upgrade()?;
+        let guard = lock.read_auto().ok()?;
+        Some(thunk(guard))
+    }
+
+    /// Exclusive counterpart of [`WeakRwLock::with_read_guard`].
+    pub fn with_write_guard<F, Ret>(&self, thunk: F) -> Option<Ret>
+    where
+        F: FnOnce(AutoWriteGuard<'_, T>) -> Ret,
+    {
+        let lock = self.upgrade()?;
+        let guard = lock.write_auto().ok()?;
+        Some(thunk(guard))
+    }
+}
+
+impl<T> Clone for WeakRwLock<T> {
+    fn clone(&self) -> Self {
+        WeakRwLock(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weak_handle_does_not_keep_lock_alive() {
+        let lock = Arc::new(RwLock::new(1u32));
+        let weak = WeakRwLock::new(&lock);
+        assert_eq!(weak.try_write(|v| *v += 1), Some(()));
+        assert_eq!(weak.try_read(|v| *v), Some(2));
+        assert_eq!(weak.with_read_guard(|guard| *guard), Some(2));
+
+        drop(lock);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.try_read(|v| *v), None);
+        assert_eq!(weak.try_write(|v| *v += 1), None);
+    }
+}
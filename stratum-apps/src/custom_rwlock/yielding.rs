@@ -0,0 +1,128 @@
+//! Long reads that let waiting writers through at points chosen by the reader.
+
+use super::{ReadGuard, RwLock};
+use std::{ops::Deref, sync::PoisonError, thread};
+
+/// Handle given to [`RwLock::read_yielding`] closures.
+///
+/// Dereferences to the protected value. [`YieldingReader::yield_lock`] briefly releases the shared
+/// lock, which requires `&mut self`, so no reference to the value can be held across a yield.
+pub struct YieldingReader<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    guard: Option<ReadGuard<'a, T>>,
+    yields: usize,
+}
+
+impl<T: ?Sized> YieldingReader<'_, T> {
+    /// Releases the shared lock, lets pending writers run, and acquires it again.
+    ///
+    /// The value may have changed once this returns. If a writer panicked while the lock was
+    /// released, the lock is still reacquired so the read can go on, and the poisoning is
+    /// reported.
+    #[track_caller]
+    pub fn yield_lock(&mut self) -> Result<(), PoisonError<()>> {
+        if let Some(guard) = self.guard.take() {
+            guard.release();
+        }
+        self.yields += 1;
+        thread::yield_now();
+        match self.lock.read() {
+            Ok(guard) => {
+                self.guard = Some(guard);
+                Ok(())
+            }
+            Err(poisoned) => {
+                self.guard = Some(ReadGuard::new(self.lock, poisoned.into_inner()));
+                Err(PoisonError::new(()))
+            }
+        }
+    }
+
+    /// Number of yields so far.
+    pub fn yields(&self) -> usize {
+        self.yields
+    }
+
+    /// Yields the lock like [`YieldingReader::yield_lock`], but only if a priority writer is
+    /// blocked behind the readers, see [`RwLock::waiting_writer_priority_blocked`].
+    ///
+    /// Returns whether the lock was yielded.
+    #[track_caller]
+    pub fn yield_if_priority_blocked(&mut self) -> Result<bool, PoisonError<()>> {
+        if !self.lock.waiting_writer_priority_blocked() {
+            return Ok(false);
+        }
+        self.yield_lock().map(|()| true)
+    }
+}
+
+impl<T: ?Sized> Deref for YieldingReader<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard
+            .as_ref()
+            .expect("the lock is held between yields")
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Runs a long read under a shared lock that `f` can periodically give up.
+    ///
+    /// Every [`YieldingReader::yield_lock`] call is a safe point where writers waiting for the
+    /// lock get to run, which keeps a long iteration from starving them. The lock is released
+    /// once `f` returns, or while unwinding if it panics.
+    #[track_caller]
+    pub fn read_yielding<F, Ret>(&self, f: F) -> Result<Ret, PoisonError<()>>
+    where
+        F: FnOnce(&mut YieldingReader<'_, T>) -> Ret,
+    {
+        let guard = self.read().map_err(|_| PoisonError::new(()))?;
+        let mut reader = YieldingReader {
+            lock: self,
+            guard: Some(guard),
+            yields: 0,
+        };
+        let result = f(&mut reader);
+        if let Some(guard) = reader.guard.take() {
+            guard.release();
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_waiting_writer_progresses_mid_read() {
+        let lock = RwLock::new(0u32);
+        let written = AtomicBool::new(false);
+        let seen = thread::scope(|s| {
+            lock.read_yielding(|reader| {
+                s.spawn(|| {
+                    lock.super_safe_write(|v| *v = 1);
+                    written.store(true, Ordering::Release);
+                });
+                let mut seen = Vec::new();
+                for _ in 0..50 {
+                    seen.push(**reader);
+                    thread::sleep(Duration::from_millis(1));
+                    reader.yield_lock().unwrap();
+                }
+                assert_eq!(reader.yields(), 50);
+                seen
+            })
+            .unwrap()
+        });
+        assert!(written.load(Ordering::Acquire));
+        // The writer got in during the read, not after it.
+        assert_eq!(seen[0], 0);
+        assert_eq!(*seen.last().unwrap(), 1);
+    }
+}
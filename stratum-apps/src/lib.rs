@@ -10,6 +10,13 @@
 //! - `network` - High-level networking utilities (enabled by default)
 //! - `config` - Configuration management helpers (enabled by default)
 //! - `rpc` - RPC utilities with custom types for JSON-RPC communication (optional)
+//! - `lock_api` - `lock_api::RawRwLock` implementation for the custom RwLock (optional)
+//! - `profile` - Per acquisition site hold time profile of the custom RwLock (optional)
+//! - `fairness` - Fairness index of the custom RwLock acquisitions across threads (optional)
+//! - `async` - Stream of write notifications of the custom RwLock for async consumers (optional)
+//! - `critical-section` - `critical_section` backed lock for targets without threads (optional)
+//! - `epoch` - Lock with lock-free reads over epoch-reclaimed versions (optional)
+//! - `test-util` - Contention harness and held guard registry for lock tests (optional)
 //!
 //! ### Role-Specific Feature Bundles
 //! - `pool` - Everything needed for pool applications
@@ -46,6 +53,10 @@ pub mod config_helpers;
 ///
 /// A wrapper around std::sync::Mutex
 pub mod custom_mutex;
+/// Custom RwLock
+///
+/// A wrapper around std::sync::RwLock
+pub mod custom_rwlock;
 /// RPC utilities for Job Declaration Server
 ///
 /// HTTP-based RPC server implementation for JD Server functionality.
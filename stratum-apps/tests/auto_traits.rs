@@ -0,0 +1,69 @@
+//! Compile time checks of the `Send`/`Sync` bounds of the custom RwLock types.
+//!
+//! The bounds follow `std::sync::RwLock` and its guards:
+//! - `RwLock<T>` is `Send` if `T: Send` and `Sync` if `T: Send + Sync`.
+//! - Guards are never `Send`, since they must be released on the thread that acquired them, and
+//!   are `Sync` if `T: Sync`, since a shared guard only hands out `&T`.
+
+use static_assertions::{assert_impl_all, assert_not_impl_any};
+use std::{cell::Cell, rc::Rc, sync::MutexGuard};
+use stratum_apps::custom_rwlock::{
+    AutoReadGuard, AutoWriteGuard, FairRwLock, FlightSlot, GuardBundle, MappedWriteGuard,
+    OwnedReadGuard, ReadGuard, RwLock, WeakRwLock, WriteGuard,
+};
+
+// `Send + !Sync`
+type SendOnly = Cell<u8>;
+// `Sync + !Send`
+type SyncOnly = MutexGuard<'static, u8>;
+// `!Send + !Sync`
+type Neither = Rc<u8>;
+
+assert_impl_all!(RwLock<u8>: Send, Sync);
+assert_impl_all!(RwLock<SendOnly>: Send);
+assert_not_impl_any!(RwLock<SendOnly>: Sync);
+assert_not_impl_any!(RwLock<SyncOnly>: Send, Sync);
+assert_not_impl_any!(RwLock<Neither>: Send, Sync);
+
+assert_impl_all!(FairRwLock<u8>: Send, Sync);
+assert_impl_all!(FairRwLock<SendOnly>: Send);
+assert_not_impl_any!(FairRwLock<SendOnly>: Sync);
+assert_not_impl_any!(FairRwLock<SyncOnly>: Send, Sync);
+
+assert_impl_all!(ReadGuard<'static, u8>: Sync);
+assert_impl_all!(ReadGuard<'static, SyncOnly>: Sync);
+assert_not_impl_any!(ReadGuard<'static, u8>: Send);
+assert_not_impl_any!(ReadGuard<'static, SendOnly>: Send, Sync);
+
+assert_impl_all!(WriteGuard<'static, u8>: Sync);
+assert_impl_all!(WriteGuard<'static, SyncOnly>: Sync);
+assert_not_impl_any!(WriteGuard<'static, u8>: Send);
+assert_not_impl_any!(WriteGuard<'static, SendOnly>: Send, Sync);
+
+assert_impl_all!(AutoReadGuard<'static, SyncOnly>: Sync);
+assert_not_impl_any!(AutoReadGuard<'static, u8>: Send);
+assert_not_impl_any!(AutoReadGuard<'static, SendOnly>: Send, Sync);
+
+assert_impl_all!(AutoWriteGuard<'static, SyncOnly>: Sync);
+assert_not_impl_any!(AutoWriteGuard<'static, u8>: Send);
+assert_not_impl_any!(AutoWriteGuard<'static, SendOnly>: Send, Sync);
+
+assert_impl_all!(GuardBundle<(ReadGuard<'static, u8>, WriteGuard<'static, u8>)>: Sync);
+assert_not_impl_any!(GuardBundle<(ReadGuard<'static, u8>, WriteGuard<'static, u8>)>: Send);
+
+// Mapped guards share the write guard through an `Rc`, so they are neither `Send` nor `Sync`.
+assert_not_impl_any!(MappedWriteGuard<'static, u8>: Send, Sync);
+
+// Owned guards hold a std guard too, which must be released by the thread that acquired it.
+assert_impl_all!(OwnedReadGuard<u8>: Sync);
+assert_not_impl_any!(OwnedReadGuard<u8>: Send);
+
+// Slots are shared between the caller computing the value and the callers waiting for it.
+assert_impl_all!(FlightSlot<SendOnly>: Send, Sync);
+assert_not_impl_any!(FlightSlot<Neither>: Send, Sync);
+
+assert_impl_all!(WeakRwLock<u8>: Send, Sync);
+assert_not_impl_any!(WeakRwLock<SendOnly>: Send, Sync);
+
+#[cfg(feature = "lock_api")]
+assert_impl_all!(stratum_apps::custom_rwlock::RawRwLock: Send, Sync);
@@ -0,0 +1,75 @@
+//! Checks that acquiring and releasing custom RwLock guards does not allocate.
+//!
+//! Guards are plain structs wrapping the std guard and some bookkeeping, so there is nothing to
+//! pool. This test keeps it that way by counting the allocations made by the current thread
+//! across many acquire/release cycles.
+//!
+//! Only locks without a deadlock timeout are covered, and the test is skipped with `test-util`:
+//! both record every acquisition in a map, which does allocate.
+
+#![cfg(not(feature = "test-util"))]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+use stratum_apps::custom_rwlock::RwLock;
+
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.with(Cell::get) {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+    ALLOCATIONS.with(|count| count.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    f();
+    COUNTING.with(|counting| counting.set(false));
+    ALLOCATIONS.with(Cell::get)
+}
+
+fn cycle(lock: &RwLock<Vec<u64>>) {
+    let mut guard = lock.write().unwrap();
+    guard[0] += 1;
+    guard.release();
+    let guard = lock.read().unwrap();
+    assert!(guard[0] > 0);
+    guard.release();
+    let guard = lock.read_auto().unwrap();
+    assert!(guard[0] > 0);
+    drop(guard);
+    lock.safe_write(|v| v[0] += 1).unwrap();
+    lock.safe_read(|v| v[0]).unwrap();
+}
+
+#[test]
+fn test_guards_do_not_allocate() {
+    let lock = RwLock::new(vec![0u64; 16]);
+    // Warm up thread local bookkeeping, which keeps its capacity afterwards.
+    cycle(&lock);
+
+    let allocations = count_allocations(|| {
+        for _ in 0..10_000 {
+            cycle(&lock);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
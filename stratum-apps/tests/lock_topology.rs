@@ -0,0 +1,56 @@
+//! Checks a lock ordering invariant over a concurrent workload with the held guard registry.
+//!
+//! The workload locks `jobs` and `shares`, sometimes nested, and must never acquire `jobs` while
+//! already holding `shares`. Every acquisition is followed by an inspection of the guards held by
+//! the acquiring thread.
+
+#![cfg(feature = "test-util")]
+
+use std::thread;
+use stratum_apps::custom_rwlock::{held_guards, RwLock};
+
+// Panics if the current thread holds `jobs` and acquired it after `shares`.
+fn assert_jobs_before_shares(jobs: &RwLock<Vec<u32>>, shares: &RwLock<u64>) {
+    let mine: Vec<_> = held_guards()
+        .into_iter()
+        .filter(|guard| guard.thread() == thread::current().id())
+        .collect();
+    let position = |lock: usize| mine.iter().position(|guard| guard.lock() == lock);
+    if let (Some(jobs), Some(shares)) = (position(jobs.addr()), position(shares.addr())) {
+        assert!(
+            jobs < shares,
+            "jobs acquired at {} while holding shares",
+            mine[jobs].site()
+        );
+    }
+}
+
+#[test]
+fn jobs_is_never_acquired_while_holding_shares() {
+    let jobs = RwLock::builder(Vec::new()).label("jobs").build().unwrap();
+    let shares = RwLock::builder(0u64).label("shares").build().unwrap();
+    thread::scope(|s| {
+        for worker in 0..4u32 {
+            let (jobs, shares) = (&jobs, &shares);
+            s.spawn(move || {
+                for i in 0..200 {
+                    if i % 2 == 0 {
+                        let mut pending = jobs.write().unwrap();
+                        assert_jobs_before_shares(jobs, shares);
+                        pending.push(worker);
+                        shares.super_safe_write(|total| {
+                            assert_jobs_before_shares(jobs, shares);
+                            *total += 1;
+                        });
+                        pending.release();
+                    } else {
+                        shares.super_safe_write(|total| *total += 1);
+                        jobs.super_safe_read(|_| assert_jobs_before_shares(jobs, shares));
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(jobs.into_inner().len(), 400);
+    assert_eq!(shares.into_inner(), 800);
+}
@@ -0,0 +1,18 @@
+//! Checks the crate-global outstanding guard assertion used for test teardown.
+//!
+//! The count is shared by every lock of the process, so this binary only holds a single test:
+//! no other test can be holding guards while it is checked.
+
+use std::panic::catch_unwind;
+use stratum_apps::custom_rwlock::{assert_no_outstanding_guards, RwLock};
+
+#[test]
+fn leaked_guard_fails_the_teardown_assertion() {
+    let lock = RwLock::new(0u8);
+    lock.read().unwrap().release();
+    lock.safe_write(|v| *v += 1).unwrap();
+    assert_no_outstanding_guards();
+
+    std::mem::forget(lock.read().unwrap());
+    assert!(catch_unwind(assert_no_outstanding_guards).is_err());
+}
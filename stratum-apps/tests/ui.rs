@@ -0,0 +1,6 @@
+//! Checks that misuses of the custom RwLock guards are rejected at compile time.
+
+#[test]
+fn ui() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,15 @@
+use stratum_apps::custom_rwlock::RwLock;
+
+fn require_send<F: std::future::Future + Send>(_: F) {}
+
+async fn yield_point() {}
+
+fn main() {
+    let lock = RwLock::new(0u32);
+    require_send(async {
+        let mut guard = lock.write().unwrap();
+        *guard += 1;
+        yield_point().await;
+        guard.release();
+    });
+}